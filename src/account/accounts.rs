@@ -1,5 +1,7 @@
 use crate::client::OandaClient;
+use crate::endpoint::Endpoint;
 use crate::error::APIError;
+use crate::response::Response;
 use serde::{Serialize, Deserialize};
 
 
@@ -17,33 +19,43 @@ pub struct AccountsResponse {
 }
 
 
+/// `GET /v3/accounts`
+#[derive(Debug, Clone, Default)]
+pub struct GetAccounts;
+
+impl Endpoint for GetAccounts {
+    type Response = AccountsResponse;
+
+    fn path(&self) -> String {
+        "/v3/accounts".to_string()
+    }
+}
+
+
 impl OandaClient {
     /// Get a list of all Accounts authorized for the provided token.
     pub async fn get_accounts(&mut self) -> Result<AccountsResponse, APIError> {
-        let url = "/v3/accounts".to_string();
-
-        let response = OandaClient::check_response(
-            self.get(&url).await
-        ).await?;
+        self.execute(&GetAccounts).await
+    }
 
-        let accounts: AccountsResponse = serde_json::from_value(response).map_err(APIError::from)?;
-        Ok(accounts)
+    pub async fn get_accounts_with_response(&mut self) -> Result<Response<AccountsResponse>, APIError> {
+        self.execute_with_response(&GetAccounts).await
     }
 }
 
-
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::len_zero)]
 mod tests {
 
     #[allow(unused_imports)]
     use super::*;
+    use crate::mock::MockServer;
 
 
     #[tokio::test]
     async fn test_get_accounts() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY")
-            .expect("OANDA_API_KEY must be set");
-        let mut client = OandaClient::new(None, &api_key, 100, 100, 100, 5).unwrap();
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
         
         match client.get_accounts().await {
             Ok(response) => {
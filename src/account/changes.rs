@@ -1,7 +1,13 @@
+use std::collections::HashMap;
+
 use serde::{Serialize, Deserialize};
 
 use crate::client::OandaClient;
+use crate::endpoint::Endpoint;
 use crate::error::APIError;
+use crate::response::Response;
+use crate::primitives::datetime::DateTime;
+use crate::primitives::decimal::{AccountUnits, DecimalNumber, PriceValue};
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,6 +29,7 @@ pub struct Changes {
     pub tradesClosed: Vec<Trade>,
     pub tradesOpened: Vec<Trade>,
     pub tradesReduced: Vec<Trade>,
+    #[serde(rename = "transactions")]
     pub trasactions: Vec<Transaction>,
 }
 
@@ -30,8 +37,10 @@ pub struct Changes {
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Order {
-    pub createTime: String,
-    pub filledTime: String,
+    #[serde(with = "crate::primitives::datetime")]
+    pub createTime: DateTime,
+    #[serde(with = "crate::primitives::datetime")]
+    pub filledTime: DateTime,
     pub fillingTransactionID: String,
     pub id: String,
     pub instrument: String,
@@ -40,7 +49,7 @@ pub struct Order {
     pub timeInForce: String,
     pub tradeOpenedID: String,
     pub type_: String,
-    pub units: String,
+    pub units: DecimalNumber,
 }
 
 
@@ -49,8 +58,8 @@ pub struct Order {
 pub struct Position {
     pub instrument: String,
     pub long: PositionDetails,
-    pub pl: String,
-    pub resettablePL: String,
+    pub pl: AccountUnits,
+    pub resettablePL: AccountUnits,
     pub short: PositionDetails,
 }
 
@@ -59,26 +68,27 @@ pub struct Position {
 #[allow(non_snake_case)]
 pub struct PositionDetails {
     #[serde(default)]
-    pub averagePrice: String,
-    pub pl: String,
-    pub resettablePL: String,
+    pub averagePrice: PriceValue,
+    pub pl: AccountUnits,
+    pub resettablePL: AccountUnits,
     #[serde(default)]
     pub tradeIDs: Vec<String>,
-    pub units: String,
+    pub units: DecimalNumber,
 }
 
 
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Trade {
-    pub currentUnits: String,
-    pub financing: String,
+    pub currentUnits: DecimalNumber,
+    pub financing: AccountUnits,
     pub id: String,
-    pub initialUnits: String,
+    pub initialUnits: DecimalNumber,
     pub instrument: String,
-    pub openTime: String,
-    pub price: String,
-    pub realizedPL: String,
+    #[serde(with = "crate::primitives::datetime")]
+    pub openTime: DateTime,
+    pub price: PriceValue,
+    pub realizedPL: AccountUnits,
     pub state: String,
 }
 
@@ -99,30 +109,32 @@ pub struct MarketOrderTransaction {
     pub instrument: String,
     pub positionFill: String,
     pub reason: String,
-    pub time: String,
+    #[serde(with = "crate::primitives::datetime")]
+    pub time: DateTime,
     pub timeInForce: String,
     pub r#type: String,
-    pub units: String,
+    pub units: DecimalNumber,
     pub userID: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct OrderFillTransaction {
-    pub accountBalance: String,
+    pub accountBalance: AccountUnits,
     pub accountID: String,
     pub batchID: String,
-    pub financing: String,
+    pub financing: AccountUnits,
     pub id: String,
     pub instrument: String,
     pub orderID: String,
-    pub pl: String,
-    pub price: String,
+    pub pl: AccountUnits,
+    pub price: PriceValue,
     pub reason: String,
-    pub time: String,
+    #[serde(with = "crate::primitives::datetime")]
+    pub time: DateTime,
     pub tradeOpened: TradeOpened,
     pub r#type: String,
-    pub units: String,
+    pub units: DecimalNumber,
     pub userID: u32,
 }
 
@@ -130,87 +142,91 @@ pub struct OrderFillTransaction {
 #[allow(non_snake_case)]
 pub struct TradeOpened {
     pub tradeID: String,
-    pub units: String,
+    pub units: DecimalNumber,
 }
 
 
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct State {
-    pub NAV: String,
-    pub marginAvailable: String,
-    pub marginCloseoutMarginUsed: String,
-    pub marginCloseoutNAV: String,
-    pub marginCloseoutPercent: String,
-    pub marginCloseoutUnrealizedPL: String,
-    pub marginUsed: String,
+    pub NAV: AccountUnits,
+    pub marginAvailable: AccountUnits,
+    pub marginCloseoutMarginUsed: AccountUnits,
+    pub marginCloseoutNAV: AccountUnits,
+    pub marginCloseoutPercent: DecimalNumber,
+    pub marginCloseoutUnrealizedPL: AccountUnits,
+    pub marginUsed: AccountUnits,
     pub orders: Vec<String>,
-    pub positionValue: String,
+    pub positionValue: AccountUnits,
     pub positions: Vec<StatePosition>,
     pub trades: Vec<StateTrade>,
-    pub unrealizedPL: String,
-    pub withdrawalLimit: String,
+    pub unrealizedPL: AccountUnits,
+    pub withdrawalLimit: AccountUnits,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct StatePosition {
     pub instrument: String,
-    pub longUnrealizedPL: String,
-    pub netUnrealizedPL: String,
-    pub shortUnrealizedPL: String,
+    pub longUnrealizedPL: AccountUnits,
+    pub netUnrealizedPL: AccountUnits,
+    pub shortUnrealizedPL: AccountUnits,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct StateTrade {
     pub id: String,
-    pub unrealizedPL: String,
+    pub unrealizedPL: AccountUnits,
 }
 
 
 
+/// `GET /v3/accounts/{accountID}/changes`
+#[derive(Debug, Clone)]
+pub struct GetAccountChanges {
+    pub since_transaction_id: String,
+}
+
+impl Endpoint for GetAccountChanges {
+    type Response = ChangesResponse;
+
+    fn path(&self) -> String {
+        "/v3/accounts/{accountID}/changes".to_string()
+    }
+
+    fn query(&self) -> Option<HashMap<String, String>> {
+        Some(HashMap::from([
+            ("sinceTransactionID".to_string(), self.since_transaction_id.clone()),
+        ]))
+    }
+}
+
+
 impl OandaClient {
     /// Endpoint used to poll an Account for its current state and changes since a specified TransactionID
     // TODO: test this function with a valid transaction_id
-    pub async fn get_changes(&mut self, transaction_id: &String) -> Result<ChangesResponse, APIError> {
-        if let Some(account_id) = self.get_account_id() {
-            let url = format!(
-                "/v3/accounts/{}/changes?sinceTransactionID={}",
-                account_id,
-                transaction_id
-            );
-            let response = OandaClient::check_response(
-                self.get(&url).await
-            ).await?;
-            let changes : ChangesResponse = serde_json::from_value(response).map_err(APIError::from)?;
-            Ok(changes)
-        } else {
-            Err(APIError::Other("Account ID Not Set".to_string()))
-        }
+    pub async fn get_changes(&mut self, transaction_id: &str) -> Result<ChangesResponse, APIError> {
+        self.execute(&GetAccountChanges { since_transaction_id: transaction_id.to_string() }).await
     }
-}
 
+    pub async fn get_changes_with_response(&mut self, transaction_id: &str) -> Result<Response<ChangesResponse>, APIError> {
+        self.execute_with_response(&GetAccountChanges { since_transaction_id: transaction_id.to_string() }).await
+    }
+}
 
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
 
     #[allow(unused_imports)]
     use super::*;
+    use crate::mock::MockServer;
 
     #[tokio::test]
     async fn test_get_changes() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY").expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID").expect("OANDA_ACCOUNT_ID must be set");
-        let mut client = OandaClient::new(
-                    Some(&account_id), 
-                    &api_key, 
-                    100,
-                    100,
-                    100,
-                    5
-                )
-                .unwrap();
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
         let transaction_id = "6357".to_string();
 
         match client.get_changes(&transaction_id).await {
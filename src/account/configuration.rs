@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use reqwest::Method;
 
 use crate::client::OandaClient;
+use crate::endpoint::Endpoint;
 use crate::error::APIError;
+use crate::response::Response;
+use crate::primitives::datetime::DateTime;
+use crate::primitives::decimal::DecimalNumber;
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -11,9 +16,10 @@ pub struct ClientConfigureTransaction {
     pub accountID: String,
     pub batchID: String,
     pub id: String,
-    pub marginRate: Option<String>,
+    pub marginRate: Option<DecimalNumber>,
     pub alias: Option<String>,
-    pub time: String,
+    #[serde(with = "crate::primitives::datetime")]
+    pub time: DateTime,
     #[serde(rename = "type")]
     pub type_: String,
     pub userID: u64,
@@ -27,30 +33,84 @@ pub struct ConfigurationResponse {
 }
 
 
+/// `PATCH /v3/accounts/{accountID}/configuration`
+#[derive(Debug, Clone, Default)]
+pub struct PatchAccountConfiguration {
+    pub alias: Option<String>,
+    pub margin_rate: Option<String>,
+}
+
+impl Endpoint for PatchAccountConfiguration {
+    type Response = ConfigurationResponse;
+
+    fn method(&self) -> Method {
+        Method::PATCH
+    }
+
+    fn path(&self) -> String {
+        "/v3/accounts/{accountID}/configuration".to_string()
+    }
+
+    fn body(&self) -> Option<Value> {
+        Some(json!({
+            "alias": self.alias,
+            "marginRate": self.margin_rate
+        }))
+    }
+}
+
+
 impl OandaClient {
     /// Set the client-confguable portions of an Account.
+    /// A rejected configuration comes back as `APIError::BadRequest` or `APIError::Forbidden`
+    /// carrying the `clientConfigureRejectTransaction`.
     pub async fn patch_configuration(&mut self, alias: Option<String>, margin_rate: Option<String>) -> Result<ConfigurationResponse, APIError> {
-        if let Some(account_id) = self.get_account_id() {
-            let url = format!("/v3/accounts/{}/configuration", account_id);
-            let body = json!({
-                "alias": alias,
-                "marginRate": margin_rate
-            });
-
-            let response: Value = self.patch(&url, &body).await?;
-
-            match response.get("status").and_then(|v| v.as_u64()) {
-                Some(200) => {
-                    let response_body: ConfigurationResponse = serde_json::from_value(response)
-                        .map_err(APIError::from)?;
-                    Ok(response_body)
-                },
-                Some(400) => Err(APIError::Other("The configuration specification was invalid".to_string())),
-                Some(403) => Err(APIError::Other("The configuration operation was forbidden on the Account".to_string())),
-                _ => Err(APIError::Other("Unknown error".to_string())),
+        self.execute(&PatchAccountConfiguration { alias, margin_rate }).await
+    }
+
+    pub async fn patch_configuration_with_response(&mut self, alias: Option<String>, margin_rate: Option<String>) -> Result<Response<ConfigurationResponse>, APIError> {
+        self.execute_with_response(&PatchAccountConfiguration { alias, margin_rate }).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn test_patch_configuration() {
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
+
+        let response = client.patch_configuration(Some("Testing".to_string()), None).await;
+        match response {
+            Ok(v) => {
+                println!("Response: {:?}", v);
+                assert_eq!(v.clientConfigureTransaction.alias.as_deref(), Some("Testing"));
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                panic!("request failed");
             }
-        } else {
-            Err(APIError::Other("Account ID Not Set".to_string()))
         }
+
+        let request = &server.received_requests()[0];
+        assert_eq!(request.method, Method::PATCH);
+        assert_eq!(request.json().unwrap()["alias"], "Testing");
+    }
+
+    #[tokio::test]
+    async fn test_patch_configuration_rejected() {
+        let server = MockServer::start().await;
+        server
+            .mock(Method::PATCH, "/v3/accounts/*/configuration")
+            .respond_with(MockResponse::error(400, Some("INVALID_MARGIN_RATE"), "Invalid margin rate"));
+        let mut client = server.client();
+
+        let error = client.patch_configuration(None, Some("5".to_string())).await.unwrap_err();
+        assert!(matches!(error, APIError::BadRequest(_)));
+        assert_eq!(error.error_response().unwrap().error_code.as_deref(), Some("INVALID_MARGIN_RATE"));
     }
 }
@@ -0,0 +1,144 @@
+use crate::client::OandaClient;
+use crate::endpoint::Endpoint;
+use crate::error::APIError;
+use crate::response::Response;
+
+
+/// Every endpoint method of `OandaClient` that works on the client's Account, handed to
+/// `$forward!` so the wrappers around the client are generated from this one list.
+macro_rules! account_methods {
+    ($forward:ident) => {
+        $forward! {
+            /// Get a summary for the Account.
+            fn get_account_summary(&mut self) -> $crate::account::summary::AccountSummaryResponse;
+            fn get_account_summary_with_response(&mut self) -> $crate::response::Response<$crate::account::summary::AccountSummaryResponse>;
+            /// Get the full details for the Account.
+            fn get_account(&mut self) -> $crate::account::detail::AccountResponse;
+            fn get_account_with_response(&mut self) -> $crate::response::Response<$crate::account::detail::AccountResponse>;
+            /// Get a list of tradeable instruments for the Account.
+            fn get_account_instruments(&mut self) -> $crate::account::instruments::InstrumentsResponse;
+            fn get_account_instruments_with_response(&mut self) -> $crate::response::Response<$crate::account::instruments::InstrumentsResponse>;
+            /// Poll the Account for its current state and changes since a specified TransactionID.
+            fn get_changes(&mut self, transaction_id: &str) -> $crate::account::changes::ChangesResponse;
+            fn get_changes_with_response(&mut self, transaction_id: &str) -> $crate::response::Response<$crate::account::changes::ChangesResponse>;
+            /// Set the client-configurable portions of the Account.
+            fn patch_configuration(&mut self, alias: Option<String>, margin_rate: Option<String>) -> $crate::account::configuration::ConfigurationResponse;
+            fn patch_configuration_with_response(&mut self, alias: Option<String>, margin_rate: Option<String>) -> $crate::response::Response<$crate::account::configuration::ConfigurationResponse>;
+        }
+    };
+}
+
+#[cfg(feature = "blocking")]
+pub(crate) use account_methods;
+
+
+/// Forwards each listed method to the client scoped to the handle's Account.
+macro_rules! handle_methods {
+    ($( $(#[$meta:meta])* fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*) -> $ret:ty; )*) => {
+        $(
+            $(#[$meta])*
+            pub async fn $name(&mut self $(, $arg: $ty)*) -> Result<$ret, APIError> {
+                self.client.$name($($arg),*).await
+            }
+        )*
+    };
+}
+
+
+/// A client scoped to one Account, for tokens that can trade several sub-accounts.
+///
+/// Handles share the rate limiter and the connection pool of the client they came from,
+/// so any number of them can be kept around:
+///
+/// ```no_run
+/// # async fn run(client: &oanda_rs::client::OandaClient) -> Result<(), oanda_rs::error::APIError> {
+/// let mut hedging = client.account("101-001-1234567-001");
+/// let mut scalping = client.account("101-001-1234567-002");
+///
+/// let (a, b) = tokio::join!(hedging.get_account_summary(), scalping.get_account_summary());
+/// println!("{} {}", a?.account.balance, b?.account.balance);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AccountHandle {
+    client: OandaClient,
+}
+
+impl OandaClient {
+    /// A handle whose account endpoints all use `account_id`. The client itself is unchanged.
+    pub fn account(&self, account_id: &str) -> AccountHandle {
+        let mut client = self.clone();
+        client.set_account_id(account_id);
+        AccountHandle { client }
+    }
+}
+
+impl AccountHandle {
+    pub fn id(&self) -> &str {
+        self.client
+            .get_account_id()
+            .map(|id| id.as_str())
+            .unwrap_or_default()
+    }
+
+    /// The client scoped to this Account, for endpoints the handle does not wrap.
+    pub fn client(&mut self) -> &mut OandaClient {
+        &mut self.client
+    }
+
+    /// Send any `Endpoint`, with `{accountID}` filled in from this handle.
+    pub async fn execute<E: Endpoint>(&mut self, endpoint: &E) -> Result<E::Response, APIError> {
+        self.client.execute(endpoint).await
+    }
+
+    pub async fn execute_with_response<E: Endpoint>(&mut self, endpoint: &E) -> Result<Response<E::Response>, APIError> {
+        self.client.execute_with_response(endpoint).await
+    }
+
+    account_methods!(handle_methods);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use crate::mock::MockServer;
+
+    #[tokio::test]
+    async fn test_account_handles() {
+        let server = MockServer::start().await.with_fixtures();
+        let client = server.client();
+
+        let mut first = client.account("101-001-1234567-001");
+        let mut second = client.account("101-001-1234567-002");
+        assert_eq!(second.id(), "101-001-1234567-002");
+
+        let (a, b) = tokio::join!(first.get_account_summary(), second.get_account_instruments());
+        a.unwrap();
+        b.unwrap();
+        second.get_changes("6357").await.unwrap();
+
+        let paths: Vec<String> = server.received_requests().into_iter().map(|r| r.path).collect();
+        assert!(paths.contains(&"/v3/accounts/101-001-1234567-001/summary".to_string()));
+        assert!(paths.contains(&"/v3/accounts/101-001-1234567-002/instruments".to_string()));
+        assert!(paths.contains(&"/v3/accounts/101-001-1234567-002/changes".to_string()));
+        assert_eq!(server.hits(Method::GET, "/v3/accounts/*/summary"), 1);
+
+        // The client keeps its own account.
+        assert_eq!(client.get_account_id().map(|id| id.as_str()), Some(crate::mock::MOCK_ACCOUNT_ID));
+    }
+
+    #[tokio::test]
+    async fn test_client_without_account_id() {
+        let server = MockServer::start().await.with_fixtures();
+        let mut config = server.client_builder().config().clone();
+        config.account_id = None;
+        let mut client = crate::config::OandaClientBuilder::from_config(config).build().unwrap();
+
+        assert!(client.get_account_summary().await.is_err());
+        client.account("101-001-1234567-003").get_account_summary().await.unwrap();
+        assert_eq!(server.received_requests()[0].path, "/v3/accounts/101-001-1234567-003/summary");
+    }
+}
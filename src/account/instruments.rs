@@ -1,5 +1,8 @@
 use crate::client::OandaClient;
+use crate::endpoint::Endpoint;
 use crate::error::APIError;
+use crate::response::Response;
+use crate::primitives::decimal::DecimalNumber;
 
 use serde::{Serialize, Deserialize};
 
@@ -18,8 +21,8 @@ pub struct FinaningDay {
 #[allow(non_snake_case)]
 pub struct Financing {
     pub financingDaysOfWeek: Vec<FinaningDay>,
-    pub longRate: String,
-    pub shortRate: String,
+    pub longRate: DecimalNumber,
+    pub shortRate: DecimalNumber,
 }
 
 
@@ -40,12 +43,12 @@ pub struct Instrument {
     pub displayPrecision: u32,
     pub financing: Financing,
     pub guaranteedStopLossOrderMode: String,
-    pub marginRate: String,
-    pub maximumOrderUnits: String,
-    pub maximumPositionSize: String,
-    pub maximumTrailingStopDistance: String,
-    pub minimumTradeSize: String,
-    pub minimumTrailingStopDistance: String,
+    pub marginRate: DecimalNumber,
+    pub maximumOrderUnits: DecimalNumber,
+    pub maximumPositionSize: DecimalNumber,
+    pub maximumTrailingStopDistance: DecimalNumber,
+    pub minimumTradeSize: DecimalNumber,
+    pub minimumTrailingStopDistance: DecimalNumber,
     pub name: String,
     pub pipLocation: i32,
     pub tags: Vec<Tag>,
@@ -64,47 +67,46 @@ pub struct InstrumentsResponse {
 }
 
 
+/// `GET /v3/accounts/{accountID}/instruments`
+#[derive(Debug, Clone, Default)]
+pub struct GetAccountInstruments;
+
+impl Endpoint for GetAccountInstruments {
+    type Response = InstrumentsResponse;
+
+    fn path(&self) -> String {
+        "/v3/accounts/{accountID}/instruments".to_string()
+    }
+}
+
+
 impl OandaClient {
     /// Get a list of tradeable instruments for the given Account.
     /// The list of tradeable instruments is dependent on the regulatory division that the Account is located in,
     /// thus should be the same for all Accounts owned by a single user.
     pub async fn get_account_instruments(&mut self) -> Result<InstrumentsResponse, APIError> {
-        if let Some(account_id) = self.get_account_id() {
-            let url = format!("/v3/accounts/{}/instruments", account_id);
-            let response = OandaClient::check_response(
-                self.get(&url).await
-            ).await?;
-
-            let instruments: InstrumentsResponse = serde_json::from_value(response)?;
-            Ok(instruments)
-        } else {
-            Err(APIError::Other("Account ID Not Set".to_string()))
-        }
+        self.execute(&GetAccountInstruments).await
     }
-}
 
+    pub async fn get_account_instruments_with_response(&mut self) -> Result<Response<InstrumentsResponse>, APIError> {
+        self.execute_with_response(&GetAccountInstruments).await
+    }
+}
 
 
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::len_zero)]
 mod tests {
 
     #[allow(unused_imports)]
     use super::*;
+    use crate::mock::MockServer;
 
 
     #[tokio::test]
     async fn test_get_account_instruments() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY").expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID").expect("OANDA_ACCOUNT_ID must be set");
-        let mut client = OandaClient::new(
-                    Some(&account_id), 
-                    &api_key, 
-                    100,
-                    100,
-                    100,
-                    5
-                )
-                .unwrap();
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
 
         match client.get_account_instruments().await {
             Ok(response) => {
@@ -121,18 +123,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_serialize_instruments_response() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY").expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID").expect("OANDA_ACCOUNT_ID must be set");
-        let mut client = OandaClient::new(
-                    Some(&account_id), 
-                    &api_key, 
-                    100,
-                    100,
-                    100,
-                    5
-                )
-                .unwrap();
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
 
         match client.get_account_instruments().await {
             Ok(response) => {
@@ -4,3 +4,4 @@ pub mod instruments;
 pub mod changes;
 pub mod configuration;
 pub mod accounts;
+pub mod handle;
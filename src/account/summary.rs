@@ -1,6 +1,10 @@
 use serde::{Serialize, Deserialize};
 use crate::client::OandaClient;
+use crate::endpoint::Endpoint;
 use crate::error::APIError;
+use crate::response::Response;
+use crate::primitives::datetime::DateTime;
+use crate::primitives::decimal::{AccountUnits, DecimalNumber};
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,86 +19,85 @@ pub struct AccountSummaryResponse {
 #[allow(non_snake_case)]
 #[allow(dead_code)]
 pub struct AccountSummaryDetail {
-    pub NAV: String,
+    pub NAV: AccountUnits,
     pub alias: String,
-    pub balance: String,
-    pub commission: String,
+    pub balance: AccountUnits,
+    pub commission: AccountUnits,
     pub createdByUserID: u32,
-    pub createdTime: String,
+    #[serde(with = "crate::primitives::datetime")]
+    pub createdTime: DateTime,
     pub currency: String,
-    pub dividendAdjustment: String,
-    pub financing: String,
-    pub guaranteedExecutionFees: String,
+    pub dividendAdjustment: AccountUnits,
+    pub financing: AccountUnits,
+    pub guaranteedExecutionFees: AccountUnits,
     pub guaranteedStopLossOrderMode: String,
     pub hedgingEnabled: bool,
     pub id: String,
     pub lastTransactionID: String,
-    pub marginAvailable: String,
-    pub marginCallMarginUsed: String,
-    pub marginCallPercent: String,
-    pub marginCloseoutMarginUsed: String,
-    pub marginCloseoutNAV: String,
-    pub marginCloseoutPercent: String,
-    pub marginCloseoutPositionValue: String,
-    pub marginCloseoutUnrealizedPL: String,
-    pub marginRate: String,
-    pub marginUsed: String,
+    pub marginAvailable: AccountUnits,
+    pub marginCallMarginUsed: AccountUnits,
+    pub marginCallPercent: DecimalNumber,
+    pub marginCloseoutMarginUsed: AccountUnits,
+    pub marginCloseoutNAV: AccountUnits,
+    pub marginCloseoutPercent: DecimalNumber,
+    pub marginCloseoutPositionValue: DecimalNumber,
+    pub marginCloseoutUnrealizedPL: AccountUnits,
+    pub marginRate: DecimalNumber,
+    pub marginUsed: AccountUnits,
     pub openPositionCount: u32,
     pub openTradeCount: u32,
     pub pendingOrderCount: u32,
-    pub pl: String,
-    pub positionValue: String,
-    pub resettablePL: String,
-    pub resettablePLTime: String,
-    pub unrealizedPL: String,
-    pub withdrawalLimit: String,
+    pub pl: AccountUnits,
+    pub positionValue: AccountUnits,
+    pub resettablePL: AccountUnits,
+    #[serde(with = "crate::primitives::datetime")]
+    pub resettablePLTime: DateTime,
+    pub unrealizedPL: AccountUnits,
+    pub withdrawalLimit: AccountUnits,
 }
 
 
 
+/// `GET /v3/accounts/{accountID}/summary`
+#[derive(Debug, Clone, Default)]
+pub struct GetAccountSummary;
+
+impl Endpoint for GetAccountSummary {
+    type Response = AccountSummaryResponse;
+
+    fn path(&self) -> String {
+        "/v3/accounts/{accountID}/summary".to_string()
+    }
+}
+
+
 impl OandaClient {
     /// Get a summary for a single Account that a client has access to.
     pub async fn get_account_summary(&mut self) -> Result<AccountSummaryResponse, APIError> {
-        if let Some(account_id) = self.get_account_id().cloned() {
-            let url = format!("/v3/accounts/{}/summary", account_id);
-            let response = OandaClient::check_response(
-                self.get(&url).await
-            ).await?;
-            let account: AccountSummaryResponse = serde_json::from_value(response).map_err(APIError::from)?;
-            Ok(account)
-        } else {
-            Err(APIError::Other("Account ID Not Set".to_string()))
-        }
+        self.execute(&GetAccountSummary).await
     }
-    
-}
 
+    pub async fn get_account_summary_with_response(&mut self) -> Result<Response<AccountSummaryResponse>, APIError> {
+        self.execute_with_response(&GetAccountSummary).await
+    }
+}
 
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
     #[allow(unused_imports)]
     use super::*;
+    use crate::mock::{MockServer, MOCK_ACCOUNT_ID};
 
     #[tokio::test]
     async fn test_get_account_summary() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY")
-            .expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID")
-            .expect("OANDA_ACCOUNT_ID must be set");
-        let mut client = OandaClient::new(
-                    Some(&account_id), 
-                    &api_key, 
-                    100,
-                    100,
-                    100,
-                    5
-                )
-                .unwrap();
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
 
         match client.get_account_summary().await {
             Ok(response) => {
                 println!("Response: {:?}", response);
-                assert!(response.account.id == account_id);
+                assert!(response.account.id == MOCK_ACCOUNT_ID);
             }
             Err(e) => {
                 println!("Error: {:?}", e);
@@ -0,0 +1,292 @@
+//! A synchronous facade over `OandaClient`, for scripts and tools without an async runtime.
+//!
+//! ```no_run
+//! use oanda_rs::client::OandaClient;
+//!
+//! # fn main() -> Result<(), oanda_rs::error::APIError> {
+//! let mut client = OandaClient::builder()
+//!     .api_key("my-token")
+//!     .account_id("101-001-1234567-001")
+//!     .build_blocking()?;
+//!
+//! let summary = client.get_account_summary()?;
+//! println!("{}", summary.account.balance);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each call runs the async client on an internal runtime and blocks until it finishes, so the
+//! types and errors are the same as `OandaClient`'s. Like `reqwest::blocking`, it must not be
+//! used, or dropped, from within an async runtime.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::account::accounts::AccountsResponse;
+use crate::account::handle::{account_methods, AccountHandle};
+use crate::client::OandaClient;
+use crate::config::OandaClientBuilder;
+use crate::credentials::ApiToken;
+use crate::endpoint::Endpoint;
+use crate::environment::Environment;
+use crate::error::APIError;
+use crate::instrument::candles::{CandleQuery, CandlesResponse};
+use crate::policies::circuit_breaker::CircuitBreaker;
+use crate::policies::priority::Priority;
+use crate::response::Response;
+
+
+/// Forwards each listed method to the async client and blocks on it.
+macro_rules! blocking_methods {
+    ($( $(#[$meta:meta])* fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*) -> $ret:ty; )*) => {
+        $(
+            $(#[$meta])*
+            pub fn $name(&mut self $(, $arg: $ty)*) -> Result<$ret, APIError> {
+                self.runtime.block_on(self.client.$name($($arg),*))
+            }
+        )*
+    };
+}
+
+
+/// The blocking counterpart of `OandaClient`. Clones share the runtime and the request stack.
+#[derive(Clone)]
+pub struct BlockingOandaClient {
+    runtime: Arc<Runtime>,
+    client: OandaClient,
+}
+
+impl BlockingOandaClient {
+    pub fn builder() -> OandaClientBuilder {
+        OandaClientBuilder::new()
+    }
+
+    /// Validate the builder's configuration and build the client on a new internal runtime.
+    pub fn from_builder(builder: OandaClientBuilder) -> Result<BlockingOandaClient, APIError> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("oanda-rs-blocking")
+            .enable_all()
+            .build()
+            .map_err(|e| APIError::Other(format!("Failed to start the runtime: {}", e)))?;
+
+        // The request stack spawns its buffer worker, so it has to be built inside the runtime.
+        let client = {
+            let _guard = runtime.enter();
+            builder.build()?
+        };
+
+        Ok(BlockingOandaClient {
+            runtime: Arc::new(runtime),
+            client,
+        })
+    }
+
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.client = self.client.with_environment(environment);
+        self
+    }
+
+    /// See `OandaClient::with_timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.with_timeout(timeout);
+        self
+    }
+
+    /// See `OandaClient::with_deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.client = self.client.with_deadline(tokio::time::Instant::from_std(deadline));
+        self
+    }
+
+    /// See `OandaClient::with_priority`.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.client = self.client.with_priority(priority);
+        self
+    }
+
+    pub fn get_environment(&self) -> &Environment {
+        self.client.get_environment()
+    }
+
+    pub fn set_account_id(&mut self, account_id: &str) {
+        self.client.set_account_id(account_id);
+    }
+
+    pub fn get_account_id(&self) -> Option<&String> {
+        self.client.get_account_id()
+    }
+
+    /// The async client this one drives.
+    pub fn as_async(&self) -> &OandaClient {
+        &self.client
+    }
+
+    /// See `OandaClient::account`.
+    pub fn account(&self, account_id: &str) -> BlockingAccountHandle {
+        BlockingAccountHandle {
+            runtime: self.runtime.clone(),
+            client: self.client.account(account_id),
+        }
+    }
+
+    /// See `OandaClient::rotate_api_key`.
+    pub fn rotate_api_key(&self, token: impl Into<ApiToken>) -> Result<(), APIError> {
+        self.client.rotate_api_key(token)
+    }
+
+    /// See `OandaClient::reload_api_key`.
+    pub fn reload_api_key(&self) -> Result<(), APIError> {
+        self.client.reload_api_key()
+    }
+
+    /// See `OandaClient::current_rate_limit`.
+    pub fn current_rate_limit(&self) -> f64 {
+        self.client.current_rate_limit()
+    }
+
+    /// See `OandaClient::circuit_breaker`. Its receivers can be read with `blocking_recv`.
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.client.circuit_breaker()
+    }
+
+    pub fn execute<E: Endpoint>(&mut self, endpoint: &E) -> Result<E::Response, APIError> {
+        self.runtime.block_on(self.client.execute(endpoint))
+    }
+
+    pub fn execute_with_response<E: Endpoint>(&mut self, endpoint: &E) -> Result<Response<E::Response>, APIError> {
+        self.runtime.block_on(self.client.execute_with_response(endpoint))
+    }
+
+    pub fn request<T: DeserializeOwned>(
+        &mut self,
+        method: Method,
+        path: &str,
+        query: Option<&HashMap<String, String>>,
+        body: Option<&Value>,
+    ) -> Result<T, APIError> {
+        self.runtime.block_on(self.client.request(method, path, query, body))
+    }
+
+    blocking_methods! {
+        fn get(&mut self, url: &str) -> Value;
+        fn post(&mut self, url: &str, body: &Value) -> Value;
+        fn put(&mut self, url: &str, body: &Value) -> Value;
+        fn patch(&mut self, url: &str, body: &Value) -> Value;
+        fn delete(&mut self, url: &str) -> Value;
+
+        /// Get a list of all Accounts authorized for the provided token.
+        fn get_accounts(&mut self) -> AccountsResponse;
+        fn get_accounts_with_response(&mut self) -> Response<AccountsResponse>;
+    }
+
+    account_methods!(blocking_methods);
+
+    blocking_methods! {
+        fn get_candles(&mut self, instrument: &str, query: impl Into<CandleQuery>) -> CandlesResponse;
+        fn get_candles_with_response(&mut self, instrument: &str, query: impl Into<CandleQuery>) -> Response<CandlesResponse>;
+    }
+}
+
+
+/// The blocking counterpart of `AccountHandle`.
+#[derive(Clone)]
+pub struct BlockingAccountHandle {
+    runtime: Arc<Runtime>,
+    client: AccountHandle,
+}
+
+impl BlockingAccountHandle {
+    pub fn id(&self) -> &str {
+        self.client.id()
+    }
+
+    pub fn execute<E: Endpoint>(&mut self, endpoint: &E) -> Result<E::Response, APIError> {
+        self.runtime.block_on(self.client.execute(endpoint))
+    }
+
+    pub fn execute_with_response<E: Endpoint>(&mut self, endpoint: &E) -> Result<Response<E::Response>, APIError> {
+        self.runtime.block_on(self.client.execute_with_response(endpoint))
+    }
+
+    account_methods!(blocking_methods);
+}
+
+
+impl OandaClientBuilder {
+    /// Validate the configuration and build a `BlockingOandaClient`.
+    /// Must be called outside of any async runtime.
+    pub fn build_blocking(self) -> Result<BlockingOandaClient, APIError> {
+        BlockingOandaClient::from_builder(self)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::APIError;
+    use crate::instrument::candles::{CandleQuery, CandleQueryParam, Granularity};
+    use crate::mock::{MockResponse, MockServer, MOCK_ACCOUNT_ID};
+
+    #[test]
+    fn test_blocking_client() {
+        // The mock server needs a runtime of its own to keep serving while the test blocks.
+        let server_runtime = Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap();
+        let server = server_runtime.block_on(MockServer::start()).with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/instruments")
+            .respond_with(MockResponse::error(401, None, "Insufficient authorization to perform request."));
+
+        let mut client = server.client_builder().build_blocking().unwrap();
+
+        let accounts = client.get_accounts().unwrap();
+        assert_eq!(accounts.accounts[0].id, MOCK_ACCOUNT_ID);
+
+        let summary = client.get_account_summary_with_response().unwrap();
+        assert!(summary.request_id().is_some());
+
+        let mut query = CandleQuery::new();
+        query.add_param("count", CandleQueryParam::Count(3));
+        query.add_param("granularity", CandleQueryParam::Granularity(Granularity::H1));
+        let candles = client.clone().get_candles("EUR_USD", query.build()).unwrap();
+        assert_eq!(candles.candles.len(), 3);
+
+        let error = client.get_account_instruments().unwrap_err();
+        assert!(matches!(error, APIError::Unauthorized(_)));
+
+        drop(client);
+        drop(server);
+    }
+
+    #[test]
+    fn test_blocking_rotation_and_handles() {
+        let server_runtime = Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap();
+        let server = server_runtime.block_on(MockServer::start()).with_fixtures();
+
+        let client = server.client_builder().build_blocking().unwrap();
+        let mut clone = client.clone();
+        client.rotate_api_key("rotated-token").unwrap();
+        assert!(client.rotate_api_key("").is_err());
+        clone.get_accounts().unwrap();
+
+        let mut handle = client.account("101-001-1234567-002");
+        assert_eq!(handle.id(), "101-001-1234567-002");
+        handle.get_account_summary().unwrap();
+
+        let requests = server.received_requests();
+        assert!(requests.iter().all(|r| r.header("Authorization") == Some("Bearer rotated-token")));
+        assert_eq!(requests[1].path, "/v3/accounts/101-001-1234567-002/summary");
+        assert_eq!(client.current_rate_limit(), 1000.0);
+        assert!(client.circuit_breaker().is_none());
+
+        drop((client, clone, handle));
+        drop(server);
+    }
+}
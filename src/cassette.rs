@@ -0,0 +1,356 @@
+//! Record and replay of the HTTP traffic of an `OandaClient`.
+//!
+//! In record mode every request/response pair that goes through `ClientWrapper` is appended to
+//! a JSON cassette file, with the API key redacted. In replay mode `ClientWrapper` answers from
+//! that file instead of the network, so a session seen against the practice server can be
+//! reproduced in a unit test.
+//!
+//! ```no_run
+//! use oanda_rs::client::OandaClient;
+//!
+//! # async fn run() -> Result<(), oanda_rs::error::APIError> {
+//! // Against the practice server, writing every exchange to the cassette.
+//! let mut client = OandaClient::builder()
+//!     .api_key("my-token")
+//!     .account_id("101-001-1234567-001")
+//!     .record_to("tests/cassettes/changes.json")
+//!     .build()?;
+//! client.get_changes("6357").await?;
+//!
+//! // Later, offline: the same calls are answered from the cassette.
+//! let mut client = OandaClient::builder()
+//!     .api_key("any-token")
+//!     .account_id("101-001-1234567-001")
+//!     .replay_from("tests/cassettes/changes.json")
+//!     .build()?;
+//! client.get_changes("6357").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Request, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::credentials::Credentials;
+use crate::error::APIError;
+
+/// Replaces the API key wherever it appears in a cassette.
+pub use crate::credentials::REDACTED;
+
+
+/// Where `ClientWrapper` gets its responses from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Send requests to OANDA.
+    #[default]
+    Live,
+    /// Send requests to OANDA and write every exchange to the cassette at this path.
+    Record(PathBuf),
+    /// Answer requests from the cassette at this path without touching the network.
+    Replay(PathBuf),
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path and query, without the host, so a cassette replays against any environment.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// The request/response pairs of a session, in the order they were sent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Cassette, APIError> {
+        let path = path.as_ref();
+        let contents = fs::read(path)
+            .map_err(|e| APIError::Config(format!("cannot read cassette {}: {}", path.display(), e)))?;
+        serde_json::from_slice(&contents).map_err(APIError::from)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), APIError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| APIError::Other(format!("cannot create {}: {}", parent.display(), e)))?;
+        }
+        let contents = serde_json::to_vec_pretty(self)?;
+        fs::write(path, contents)
+            .map_err(|e| APIError::Other(format!("cannot write cassette {}: {}", path.display(), e)))
+    }
+}
+
+
+/// Writes each exchange to the cassette as soon as it completes, so a session that
+/// crashes half way still leaves the requests that led up to the crash on disk.
+pub(crate) struct Recorder {
+    path: PathBuf,
+    credentials: Credentials,
+    cassette: Mutex<Cassette>,
+}
+
+impl Recorder {
+    pub(crate) fn new(path: PathBuf, credentials: Credentials) -> Recorder {
+        Recorder {
+            path,
+            credentials,
+            cassette: Mutex::new(Cassette::default()),
+        }
+    }
+
+    /// Record the exchange and hand back an equivalent response. The body is read in full.
+    pub(crate) async fn record(&self, request: RecordedRequest, response: Response) -> Result<Response, APIError> {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+
+        let interaction = Interaction {
+            request: self.redact_request(request),
+            response: RecordedResponse {
+                status: status.as_u16(),
+                headers: header_pairs(&headers),
+                body: self.redact(&String::from_utf8_lossy(&body)),
+            },
+        };
+
+        {
+            let mut cassette = self.cassette.lock().unwrap();
+            cassette.interactions.push(interaction);
+            cassette.save(&self.path)?;
+        }
+
+        build_response(status.as_u16(), &headers, body.to_vec())
+    }
+
+    fn redact(&self, text: &str) -> String {
+        self.credentials.redact(text)
+    }
+
+    fn redact_request(&self, request: RecordedRequest) -> RecordedRequest {
+        RecordedRequest {
+            path: self.redact(&request.path),
+            headers: request
+                .headers
+                .into_iter()
+                .map(|(name, value)| {
+                    if name.eq_ignore_ascii_case(AUTHORIZATION.as_str()) {
+                        (name, format!("Bearer {}", REDACTED))
+                    } else {
+                        let value = self.redact(&value);
+                        (name, value)
+                    }
+                })
+                .collect(),
+            body: request.body.map(|body| self.redact(&body)),
+            ..request
+        }
+    }
+}
+
+
+/// Serves the recorded interactions in order. Each one is played once; requests are matched on
+/// method, path, query (in any order) and body.
+pub(crate) struct Player {
+    interactions: Vec<Interaction>,
+    played: Mutex<Vec<bool>>,
+}
+
+impl Player {
+    pub(crate) fn new(cassette: Cassette) -> Player {
+        let played = vec![false; cassette.interactions.len()];
+        Player {
+            interactions: cassette.interactions,
+            played: Mutex::new(played),
+        }
+    }
+
+    pub(crate) fn replay(&self, request: &RecordedRequest) -> Result<Response, APIError> {
+        let mut played = self.played.lock().unwrap();
+        let index = self
+            .interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| !played[i] && matches(&interaction.request, request))
+            .ok_or_else(|| {
+                APIError::Other(format!("No recorded interaction left for {} {}", request.method, request.path))
+            })?;
+        played[index] = true;
+
+        let response = &self.interactions[index].response;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &response.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                headers.append(name, value);
+            }
+        }
+        build_response(response.status, &headers, response.body.clone().into_bytes())
+    }
+}
+
+
+impl RecordedRequest {
+    pub(crate) fn from_request(request: &Request) -> RecordedRequest {
+        let url = request.url();
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        RecordedRequest {
+            method: request.method().to_string(),
+            path,
+            headers: header_pairs(request.headers()),
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+        }
+    }
+}
+
+fn matches(recorded: &RecordedRequest, request: &RecordedRequest) -> bool {
+    recorded.method == request.method
+        && split_path(&recorded.path) == split_path(&request.path)
+        && recorded.body.as_deref().unwrap_or_default() == request.body.as_deref().unwrap_or_default()
+}
+
+/// The path and its query pairs, sorted, since query parameters built from a `HashMap`
+/// come out in a different order on every run.
+pub(crate) fn split_path(path: &str) -> (&str, Vec<&str>) {
+    match path.split_once('?') {
+        Some((path, query)) => {
+            let mut pairs: Vec<&str> = query.split('&').filter(|p| !p.is_empty()).collect();
+            pairs.sort_unstable();
+            (path, pairs)
+        }
+        None => (path, Vec::new()),
+    }
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+fn build_response(status: u16, headers: &HeaderMap, body: Vec<u8>) -> Result<Response, APIError> {
+    let mut builder = http::Response::builder().status(status);
+    for (name, value) in headers {
+        // The body is held in full now, so the framing headers of the original exchange no longer apply.
+        if name == http::header::TRANSFER_ENCODING || name == http::header::CONTENT_LENGTH {
+            continue;
+        }
+        builder = builder.header(name, value);
+    }
+    let response = builder
+        .body(body)
+        .map_err(|e| APIError::Other(format!("Invalid recorded response: {}", e)))?;
+    Ok(Response::from(response))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+    use crate::account::changes::ChangesResponse;
+    use crate::config::OandaClientBuilder;
+    use crate::mock::{MockServer, MOCK_ACCOUNT_ID, MOCK_API_KEY};
+
+    fn cassette_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("oanda_rs-{}-{}.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let path = cassette_path("record_then_replay");
+        let server = MockServer::start().await.with_fixtures();
+
+        let mut client = server.client_builder().record_to(&path).build().unwrap();
+        let recorded = client.get_changes("6357").await.unwrap();
+        client.get_accounts().await.unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains(MOCK_API_KEY));
+        assert!(contents.contains(REDACTED));
+
+        let cassette = Cassette::load(&path).unwrap();
+        assert_eq!(cassette.interactions.len(), 2);
+        assert_eq!(cassette.interactions[0].request.method, "GET");
+
+        // The server is gone, so every answer has to come from the cassette.
+        let environment = server.environment();
+        drop(server);
+        let mut replay = OandaClientBuilder::new()
+            .api_key("another-token")
+            .account_id(MOCK_ACCOUNT_ID)
+            .environment(environment)
+            .replay_from(&path)
+            .retry_attempts(0)
+            .build()
+            .unwrap();
+
+        let replayed: ChangesResponse = replay.get_changes("6357").await.unwrap();
+        assert_eq!(replayed.lastTransactionID, recorded.lastTransactionID);
+        assert_eq!(replay.get_accounts().await.unwrap().accounts[0].id, MOCK_ACCOUNT_ID);
+
+        let error = replay.get_accounts().await.unwrap_err();
+        assert!(error.to_string().contains("No recorded interaction"));
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_replay_error_response() {
+        let request = RecordedRequest {
+            method: Method::GET.to_string(),
+            path: "/v3/instruments/EUR_USD/candles?granularity=H1&count=5".to_string(),
+            headers: Vec::new(),
+            body: None,
+        };
+        let cassette = Cassette {
+            interactions: vec![Interaction {
+                request: request.clone(),
+                response: RecordedResponse {
+                    status: 400,
+                    headers: vec![("content-type".to_string(), "application/json".to_string())],
+                    body: r#"{"errorMessage":"Invalid value specified for 'granularity'"}"#.to_string(),
+                },
+            }],
+        };
+
+        let player = Player::new(cassette);
+        let reordered = RecordedRequest {
+            path: "/v3/instruments/EUR_USD/candles?count=5&granularity=H1".to_string(),
+            ..request
+        };
+        let response = player.replay(&reordered).unwrap();
+        assert_eq!(response.status(), 400);
+        assert!(response.text().await.unwrap().contains("granularity"));
+    }
+}
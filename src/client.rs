@@ -1,27 +1,52 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 // External crates
 use futures::future::{poll_fn, BoxFuture};
-use reqwest::{Client, RequestBuilder};
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use tokio::time::Instant;
 use tower::Service;
 
 // Local modules
+use crate::cassette::{Cassette, CassetteMode, Player, RecordedRequest, Recorder};
+use crate::config::{ClientConfig, OandaClientBuilder};
+use crate::credentials::{ApiToken, Credentials, TokenSource};
+use crate::endpoint::ACCOUNT_ID;
+use crate::environment::Environment;
 use crate::error::APIError;
-use crate::policies::rate_limiter::RateLimiter;
-use crate::utils::clonable_request::ClonableRequest;
+use crate::policies::circuit_breaker::CircuitBreaker;
+use crate::policies::coalesce::Coalescer;
+use crate::policies::priority::Priority;
+use crate::policies::rate_limiter::{RateLimiter, StackConfig};
+use crate::policies::retry_policy::RetryPolicy;
+use crate::primitives::datetime::DatetimeFormat;
+use crate::response::{Response, REQUEST_ID_HEADER};
+use crate::utils::clonable_request::{ClonableRequest, RequestContext};
 
 
 #[derive(Clone)]
 pub struct OandaClient {
     client: RateLimiter<ClientWrapper>,
     account_id: Option<String>,
-    api_key: String,
-    base_url: String,
+    credentials: Credentials,
+    environment: Environment,
+    http: Client,
+    read_timeout: Option<Duration>,
+    call_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    priority: Option<Priority>,
+    datetime_format: Option<DatetimeFormat>,
 }
 
 impl OandaClient {
 
+    /// Build a client with positional settings.
+    /// Prefer `OandaClient::builder()`, which names each setting and fills in defaults.
     pub fn new(
         account_id: Option<&str>, 
         api_key: &str, 
@@ -30,26 +55,129 @@ impl OandaClient {
         rate_limit: usize, 
         retry_attempts: usize
     ) -> Result<OandaClient, APIError> {
+        let config = ClientConfig {
+            api_key: TokenSource::Static(ApiToken::new(api_key)),
+            account_id: account_id.map(|s| s.to_string()),
+            buffer_size,
+            concurrency_limit,
+            rate_limit,
+            retry_attempts,
+            ..ClientConfig::default()
+        };
+        OandaClientBuilder::from_config(config).build()
+    }
+
+    pub fn builder() -> OandaClientBuilder {
+        OandaClientBuilder::new()
+    }
 
-        let client = Client::new();
-        let service = RateLimiter::new(
-            ClientWrapper(client), 
-            rate_limit, 
-            buffer_size, 
-            concurrency_limit, 
-            retry_attempts
+    /// Build a client from a configuration without validating it; `OandaClientBuilder::build` validates first.
+    pub(crate) fn from_config(config: ClientConfig) -> Result<OandaClient, APIError> {
+
+        let credentials = Credentials::load(config.api_key.clone())?;
+        let http = config.http_client()?;
+        let service = RateLimiter::build(
+            ClientWrapper::with_cassette(http.clone(), &config.cassette, &credentials)?,
+            StackConfig {
+                rate_limit: config.rate_limit,
+                buffer_size: config.buffer_size,
+                concurrency_limit: config.concurrency_limit,
+                priority_aging: config.priority_aging,
+                retry_policy: RetryPolicy::with_config(config.retry_attempts, config.retry.clone()),
+                circuit_breaker: config.circuit_breaker.clone(),
+                coalesce: config.coalesce_requests,
+                endpoint_limits: config.endpoint_rate_limits,
+                layers: config.layers.clone(),
+            },
         );
 
         let client = OandaClient {
             client: service?,
-            account_id: account_id.map(|s| s.to_string()),
-            api_key: api_key.to_string(),
-            base_url: "https://api-fxpractice.oanda.com".to_string(),
+            account_id: config.account_id,
+            credentials,
+            environment: config.environment,
+            http,
+            read_timeout: config.read_timeout,
+            call_timeout: config.call_timeout,
+            deadline: None,
+            priority: None,
+            datetime_format: config.datetime_format,
         };
 
         Ok(client)
     }
 
+    /// Point the client at another OANDA environment. Defaults to `Environment::Practice`.
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Give every call made through this client at most `timeout`, counting the wait in the
+    /// queue, the retries and the network. A call that runs out fails with `APIError::Timeout`.
+    ///
+    /// Clones share the request stack, so a tight budget can be set for a single call site:
+    /// `client.clone().with_timeout(Duration::from_millis(300)).get_account_summary()`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = Some(timeout);
+        self
+    }
+
+    /// Make every call made through this client fail with `APIError::Timeout` once `deadline` passes.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Send every call made through this client at `priority`, instead of the default for its
+    /// endpoint (`Critical` for orders, trades and positions, `Normal` otherwise).
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn get_environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Send `token` with every request from now on, from this client and all of its clones.
+    /// An empty token, or one that cannot be sent in a header, is rejected and the old one kept.
+    pub fn rotate_api_key(&self, token: impl Into<ApiToken>) -> Result<(), APIError> {
+        self.credentials.rotate(token.into())
+    }
+
+    /// Read the token again from the source it was configured with, and rotate to it.
+    pub fn reload_api_key(&self) -> Result<(), APIError> {
+        self.credentials.reload()
+    }
+
+    /// The request rate in use, in requests per second. It drops below the configured
+    /// `rate_limit` for a while after OANDA answers 429, for this client and all of its clones.
+    pub fn current_rate_limit(&self) -> f64 {
+        self.client.rate.current_rate()
+    }
+
+    /// The circuit breaker shared by this client and its clones, when one was configured.
+    /// Subscribe to it to hear when OANDA stops or starts answering.
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.client.circuit_breaker.as_ref()
+    }
+
+    /// The identical GETs in flight, if `coalesce_requests` is on.
+    pub fn coalescer(&self) -> Option<&Coalescer> {
+        self.client.coalescer.as_ref()
+    }
+
+    /// The token shared by this client and its clones.
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// The `Accept-Datetime-Format` sent with each request; `RFC3339` is OANDA's default.
+    pub fn get_datetime_format(&self) -> DatetimeFormat {
+        self.datetime_format.unwrap_or_default()
+    }
+
     pub fn set_account_id(&mut self, account_id: &str) {
         self.account_id = Some(account_id.to_string());
     }
@@ -58,42 +186,174 @@ impl OandaClient {
         self.account_id.as_ref()
     }
 
-    async fn send_request(&mut self, request: RequestBuilder) -> Result<Value, APIError> {
+    async fn send_request<T: DeserializeOwned>(&mut self, request: RequestBuilder, context: RequestContext) -> Result<Response<T>, APIError> {
+        match context.deadline {
+            // Dropping the call on timeout releases its buffer and concurrency slots.
+            Some(deadline) => {
+                let budget = deadline.saturating_duration_since(Instant::now());
+                tokio::time::timeout_at(deadline, self.dispatch(request, context))
+                    .await
+                    .map_err(|_| APIError::Timeout(budget))
+                    .inspect_err(trace_error)?
+            }
+            None => self.dispatch(request, context).await,
+        }
+    }
 
-        poll_fn(|cx| self.client.service.poll_ready(cx))
-            .await
-            .map_err(|e| APIError::Other(format!("Service not ready: {}", e)))?;
+    async fn dispatch<T: DeserializeOwned>(&mut self, request: RequestBuilder, context: RequestContext) -> Result<Response<T>, APIError> {
 
+        #[cfg(feature = "tracing")]
+        let started = std::time::Instant::now();
+
+        // Built before poll_ready: a ready service holds a buffer slot until `call`,
+        // so nothing may fail or wait between the two.
         let request = request
-            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header(AUTHORIZATION, self.credentials.authorization()?)
             .build()?;
 
+        poll_fn(|cx| self.client.service.poll_ready(cx))
+            .await
+            .map_err(|e| APIError::Other(format!("Service not ready: {}", e)))?;
+
         let response = self
             .client
-            .call(ClonableRequest::new(request))
+            .call(ClonableRequest::with_context(request, context))
             .await
-            .map_err(|e| APIError::Other(e.to_string()))?;
+            .inspect_err(trace_error)?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = match self.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, response.bytes())
+                .await
+                .map_err(|_| APIError::Timeout(timeout))?,
+            None => response.bytes().await,
+        }
+        .map_err(APIError::from)
+        .inspect_err(trace_error)?;
+
+        let request_id = headers.get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok());
+
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::Span::current();
+            span.record("status", status.as_u16());
+            span.record("latency_ms", started.elapsed().as_millis() as u64);
+            if let Some(request_id) = request_id {
+                span.record("request_id", request_id);
+            }
+        }
+
+        match OandaClient::decode_response(status, &body) {
+            Ok(body) => Ok(Response::new(status, headers, body)),
+            Err(mut error) => {
+                trace_error(&error);
+                if let Some(response) = error.error_response_mut() {
+                    response.request_id = request_id.map(|v| v.to_string());
+                }
+                Err(error)
+            }
+        }
+    }
 
-        let response = response
-            .json::<Value>()
+    /// Send a request to `path` on the REST host and decode the response into `T`.
+    ///
+    /// Authentication, rate limiting, retries and error mapping are applied here,
+    /// so endpoint methods only describe the call.
+    pub async fn request<T: DeserializeOwned>(
+        &mut self,
+        method: Method,
+        path: &str,
+        query: Option<&HashMap<String, String>>,
+        body: Option<&Value>,
+    ) -> Result<T, APIError> {
+        self.request_with_response(method, path, query, body)
             .await
-            .map_err(APIError::from)?;
+            .map(Response::into_inner)
+    }
+
+    /// Like `request`, but keeps the status and headers of the response.
+    pub async fn request_with_response<T: DeserializeOwned>(
+        &mut self,
+        method: Method,
+        path: &str,
+        query: Option<&HashMap<String, String>>,
+        body: Option<&Value>,
+    ) -> Result<Response<T>, APIError> {
+        let mut context = RequestContext::new(&self.endpoint_label(path));
+        context.deadline = match (self.deadline, self.call_timeout) {
+            (Some(deadline), Some(timeout)) => Some(deadline.min(context.enqueued_at + timeout)),
+            (Some(deadline), None) => Some(deadline),
+            (None, Some(timeout)) => Some(context.enqueued_at + timeout),
+            (None, None) => None,
+        };
+        if let Some(priority) = self.priority {
+            context.priority = priority;
+        }
 
-        OandaClient::check_response(Ok(response)).await
+        // Only names and IDs go into the span: the API key and request bodies are never recorded.
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "oanda.request",
+            method = %method,
+            endpoint = %context.endpoint,
+            account = self.account_id.as_deref().unwrap_or_default(),
+            attempt = tracing::field::Empty,
+            queue_wait_ms = tracing::field::Empty,
+            status = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+            request_id = tracing::field::Empty,
+        );
+
+        let full_url = format!("{}{}", self.environment.rest_url(), path);
+        let mut request = self.http.request(method, &full_url);
+        if let Some(format) = self.datetime_format {
+            request = request.header("Accept-Datetime-Format", format.as_str());
+        }
+        if let Some(query) = query {
+            request = request.query(query);
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        let response = self.send_request(request, context);
+        #[cfg(feature = "tracing")]
+        let response = tracing::Instrument::instrument(response, span);
+        response.await
+    }
+
+    /// The path with the account ID put back as `{accountID}`, so requests for the same
+    /// endpoint share a label in traces and metrics.
+    fn endpoint_label(&self, path: &str) -> String {
+        match &self.account_id {
+            Some(account_id) => path.replace(account_id.as_str(), ACCOUNT_ID),
+            None => path.to_string(),
+        }
     }
 
     pub async fn get(&mut self, url: &str) -> Result<Value, APIError> {
-        let full_url = format!("{}{}", self.base_url, url);
-        let request = Client::new().get(&full_url);
-        self.send_request(request).await
+        self.request(Method::GET, url, None, None).await
+    }
+
+    pub async fn post(&mut self, url: &str, body: &Value) -> Result<Value, APIError> {
+        self.request(Method::POST, url, None, Some(body)).await
+    }
+
+    pub async fn put(&mut self, url: &str, body: &Value) -> Result<Value, APIError> {
+        self.request(Method::PUT, url, None, Some(body)).await
     }
 
     pub async fn patch(&mut self, url: &str, body: &Value) -> Result<Value, APIError> {
-        let full_url = format!("{}{}", self.base_url, url);
-        let request = Client::new().patch(&full_url).json(body);
-        self.send_request(request).await
+        self.request(Method::PATCH, url, None, Some(body)).await
     }
 
+    pub async fn delete(&mut self, url: &str) -> Result<Value, APIError> {
+        self.request(Method::DELETE, url, None, None).await
+    }
+
+    /// Turn a decoded body that carries an `errorMessage` into an error. Requests sent through the
+    /// client are already checked by their HTTP status, with the structured `APIError` variants.
     pub async fn check_response(response: Result<Value, APIError>) -> Result<Value, APIError> {
         match response {
             Ok(value) => {
@@ -106,14 +366,62 @@ impl OandaClient {
             Err(err) => Err(err),
         }
     }
+
+    /// Decode a response body into `T`, turning a non-success status into the matching `APIError`.
+    pub(crate) fn decode_response<T: DeserializeOwned>(status: StatusCode, body: &[u8]) -> Result<T, APIError> {
+        if !status.is_success() {
+            return Err(APIError::from_status(status, body));
+        }
+        if body.is_empty() {
+            return T::deserialize(Value::Null).map_err(APIError::from);
+        }
+        serde_json::from_slice(body).map_err(APIError::from)
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn trace_error(error: &APIError) {
+    tracing::warn!(error = %error, "request failed");
 }
 
+#[cfg(not(feature = "tracing"))]
+fn trace_error(_error: &APIError) {}
+
+
+/// The bottom of the service stack: sends the request with `reqwest`, or records or replays
+/// it when a `CassetteMode` is set.
 #[derive(Clone)]
-pub struct ClientWrapper(Client);
+pub struct ClientWrapper {
+    client: Client,
+    mode: WrapperMode,
+}
+
+#[derive(Clone)]
+enum WrapperMode {
+    Live,
+    Record(Arc<Recorder>),
+    Replay(Arc<Player>),
+}
+
+impl ClientWrapper {
+    pub fn new(client: Client) -> ClientWrapper {
+        ClientWrapper { client, mode: WrapperMode::Live }
+    }
+
+    /// The current token is scrubbed from everything written in record mode.
+    pub fn with_cassette(client: Client, mode: &CassetteMode, credentials: &Credentials) -> Result<ClientWrapper, APIError> {
+        let mode = match mode {
+            CassetteMode::Live => WrapperMode::Live,
+            CassetteMode::Record(path) => WrapperMode::Record(Arc::new(Recorder::new(path.clone(), credentials.clone()))),
+            CassetteMode::Replay(path) => WrapperMode::Replay(Arc::new(Player::new(Cassette::load(path)?))),
+        };
+        Ok(ClientWrapper { client, mode })
+    }
+}
 
 impl Service<ClonableRequest> for ClientWrapper {
     type Response = reqwest::Response;
-    type Error = reqwest::Error;
+    type Error = APIError;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -121,33 +429,234 @@ impl Service<ClonableRequest> for ClientWrapper {
     }
 
     fn call(&mut self, req: ClonableRequest) -> Self::Future {
-        let client = self.0.clone();
+        let client = self.client.clone();
+        let mode = self.mode.clone();
+
+        // Runs once the request has made it through the buffer, the concurrency limit and the rate limit.
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::Span::current();
+            let queue_wait_ms = req.context.enqueued_at.elapsed().as_millis() as u64;
+            span.record("attempt", req.context.attempt);
+            if req.context.attempt == 1 {
+                span.record("queue_wait_ms", queue_wait_ms);
+            }
+            tracing::debug!(attempt = req.context.attempt, queue_wait_ms, "sending request");
+        }
+
         let request = req.into_inner();
         Box::pin(async move {
-            client.execute(request).await
+            match mode {
+                WrapperMode::Live => client.execute(request).await.map_err(APIError::from),
+                WrapperMode::Record(recorder) => {
+                    let recorded = RecordedRequest::from_request(&request);
+                    let response = client.execute(request).await?;
+                    recorder.record(recorded, response).await
+                }
+                WrapperMode::Replay(player) => player.replay(&RecordedRequest::from_request(&request)),
+            }
         })
     }
 }
 
+#[cfg(test)]
 mod tests {
+    use crate::mock::{MockServer, MOCK_ACCOUNT_ID};
+
+    #[tokio::test]
+    async fn test_clone_client() {
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
+        let mut client_clone = client.clone();
+        assert_eq!(client.get_account_id().unwrap(), client_clone.get_account_id().unwrap());
+
+        let accounts = client.get_accounts().await.unwrap();
+        let cloned_accounts = client_clone.get_accounts().await.unwrap();
+        assert_eq!(accounts.accounts[0].id, MOCK_ACCOUNT_ID);
+        assert_eq!(cloned_accounts.accounts[0].id, MOCK_ACCOUNT_ID);
+        assert_eq!(server.hits(reqwest::Method::GET, "/v3/accounts"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_call_timeout() {
+        use std::time::Duration;
+        use crate::error::APIError;
+        use crate::mock::{fixtures, MockResponse};
+
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(reqwest::Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::ok(fixtures::account_summary()).delay(Duration::from_millis(500)));
+        let client = server.client();
+
+        let started = tokio::time::Instant::now();
+        let error = client
+            .clone()
+            .with_timeout(Duration::from_millis(100))
+            .get_account_summary()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, APIError::Timeout(_)));
+        assert!(error.is_timeout());
+        assert!(started.elapsed() < Duration::from_millis(400));
+
+        let past = tokio::time::Instant::now();
+        let error = client.clone().with_deadline(past).get_accounts().await.unwrap_err();
+        assert!(matches!(error, APIError::Timeout(_)));
+
+        // Without a timeout the same call waits for the slow response.
+        client.clone().get_account_summary().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_calls_release_slots() {
+        use std::time::Duration;
+        use crate::mock::{fixtures, MockResponse};
+
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(reqwest::Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::ok(fixtures::account_summary()).delay(Duration::from_secs(5)));
+        let client = server
+            .client_builder()
+            .buffer_size(1)
+            .concurrency_limit(1)
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            let mut hurried = client.clone().with_timeout(Duration::from_millis(50));
+            assert!(hurried.get_account_summary().await.unwrap_err().is_timeout());
+        }
+
+        // Each timed out call gave its slots back, so this one is not stuck behind them.
+        let mut client = client.with_timeout(Duration::from_secs(1));
+        client.get_accounts().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_response() {
+        let value = serde_json::json!({"errorMessage": "Invalid value specified for 'accountID'"});
+        let error = super::OandaClient::check_response(Ok(value)).await.unwrap_err();
+        assert!(matches!(error, crate::error::APIError::Other(ref m) if m == "Invalid value specified for 'accountID'"));
+        assert!(super::OandaClient::check_response(Ok(serde_json::json!({}))).await.is_ok());
+    }
+
     #[test]
-    fn print_api_key() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("ANDA_API_KEY")
-            .expect("OANDA_API_KEY must be set");
-        println!("API Key: {}", api_key);
+    fn test_decode_response() {
+        use reqwest::StatusCode;
+        use crate::account::accounts::AccountsResponse;
+        use crate::error::APIError;
+
+        let body = br#"{"accounts":[{"id":"101-001-1234567-001","tags":[]}]}"#;
+        let accounts: AccountsResponse = super::OandaClient::decode_response(StatusCode::OK, body).unwrap();
+        assert_eq!(accounts.accounts[0].id, "101-001-1234567-001");
+
+        let empty: serde_json::Value = super::OandaClient::decode_response(StatusCode::NO_CONTENT, b"").unwrap();
+        assert!(empty.is_null());
+
+        let error = super::OandaClient::decode_response::<AccountsResponse>(
+            StatusCode::UNAUTHORIZED,
+            br#"{"errorMessage":"Insufficient authorization to perform request."}"#,
+        );
+        assert!(matches!(error, Err(APIError::Unauthorized(_))));
+    }
+
+    #[cfg(feature = "tracing")]
+    mod tracing_capture {
+        use std::collections::HashMap;
+        use std::fmt::Debug;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::{Arc, Mutex};
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing_core::span::Current;
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Collects every `name=value` recorded on spans and events.
+        #[derive(Clone, Default)]
+        pub struct Capture {
+            pub fields: Arc<Mutex<Vec<String>>>,
+            spans: Arc<Mutex<HashMap<u64, &'static Metadata<'static>>>>,
+            stack: Arc<Mutex<Vec<Id>>>,
+            next_id: Arc<AtomicU64>,
+        }
+
+        impl Visit for Capture {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.fields.lock().unwrap().push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                span.record(&mut self.clone());
+                let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+                self.spans.lock().unwrap().insert(id, span.metadata());
+                Id::from_u64(id)
+            }
+
+            fn record(&self, _span: &Id, values: &Record<'_>) {
+                values.record(&mut self.clone());
+            }
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                event.record(&mut self.clone());
+            }
+
+            fn enter(&self, span: &Id) {
+                self.stack.lock().unwrap().push(span.clone());
+            }
+
+            fn exit(&self, span: &Id) {
+                let mut stack = self.stack.lock().unwrap();
+                if let Some(position) = stack.iter().rposition(|id| id == span) {
+                    stack.remove(position);
+                }
+            }
+
+            fn current_span(&self) -> Current {
+                match self.stack.lock().unwrap().last() {
+                    Some(id) => Current::new(id.clone(), self.spans.lock().unwrap()[&id.into_u64()]),
+                    None => Current::none(),
+                }
+            }
+        }
     }
 
+    #[cfg(feature = "tracing")]
     #[tokio::test]
-    async fn test_clone_client() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY")
-            .expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID")
-            .expect("OANDA_ACCOUNT_ID must be set");
-        let client = super::OandaClient::new(Some(&account_id), &api_key, 5, 10, 3, 5).unwrap();
-        let client_id = client.get_account_id().unwrap();
-        let client_clone_id = client.get_account_id().unwrap();
-        assert_eq!(client_id, client_clone_id);
-    }
-}
\ No newline at end of file
+    async fn test_tracing_fields_without_credentials() {
+        use crate::mock::{MockResponse, MOCK_API_KEY};
+
+        let capture = tracing_capture::Capture::default();
+        let _guard = tracing::subscriber::set_default(capture.clone());
+
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(reqwest::Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::error(401, None, "Insufficient authorization to perform request."));
+        let mut client = server.client();
+
+        client.get_accounts().await.unwrap();
+        client.get_account_summary().await.unwrap_err();
+
+        let fields = capture.fields.lock().unwrap().join("\n");
+        assert!(fields.contains("endpoint=/v3/accounts/{accountID}/summary"));
+        assert!(fields.contains(&format!("account=\"{}\"", MOCK_ACCOUNT_ID)));
+        assert!(fields.contains("status=200"));
+        assert!(fields.contains("status=401"));
+        assert!(fields.contains("attempt=1"));
+        assert!(fields.contains("queue_wait_ms="));
+        assert!(fields.contains("latency_ms="));
+        assert!(fields.contains("request_id="));
+        assert!(!fields.contains(MOCK_API_KEY));
+    }
+}
@@ -0,0 +1,596 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::Client;
+use tower::{BoxError, Layer, Service};
+
+use crate::cassette::CassetteMode;
+use crate::client::OandaClient;
+use crate::credentials::{ApiToken, TokenSource};
+use crate::environment::Environment;
+use crate::error::APIError;
+use crate::policies::circuit_breaker::CircuitBreakerConfig;
+use crate::policies::endpoint_limits::{
+    ConnectionLimiter, EndpointClass, EndpointRateLimits, DEFAULT_CONNECTION_RATE_LIMIT,
+};
+use crate::policies::layers::{BoxRequestService, StackPosition, UserLayer};
+use crate::policies::priority::DEFAULT_PRIORITY_AGING;
+use crate::policies::retry_policy::{RetryClassifier, RetryConfig};
+use crate::primitives::datetime::DatetimeFormat;
+use crate::utils::clonable_request::ClonableRequest;
+
+
+/// OANDA allows at most 120 REST requests per second on a single token.
+pub const OANDA_MAX_REQUESTS_PER_SECOND: usize = 120;
+
+pub const DEFAULT_BUFFER_SIZE: usize = 100;
+pub const DEFAULT_CONCURRENCY_LIMIT: usize = 20;
+/// Stays below `OANDA_MAX_REQUESTS_PER_SECOND` to leave headroom for other tools on the same token.
+pub const DEFAULT_RATE_LIMIT: usize = 100;
+pub const DEFAULT_RETRY_ATTEMPTS: usize = 3;
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+
+/// Every setting used to build an `OandaClient`.
+///
+/// Start from `ClientConfig::default()` and override what you need,
+/// or use `OandaClientBuilder` which validates the values on `build`.
+#[derive(Clone)]
+pub struct ClientConfig {
+    /// Where the API token comes from. It is read once on `build` and again on `OandaClient::reload_api_key`.
+    pub api_key: TokenSource,
+    pub account_id: Option<String>,
+    pub environment: Environment,
+    /// Number of requests that can wait for a concurrency slot in the priority scheduler's
+    /// queues. Once they are full, further calls wait in their own task until there is room.
+    pub buffer_size: usize,
+    /// Maximum number of requests in flight at the same time.
+    pub concurrency_limit: usize,
+    /// How long a request waiting for a concurrency slot takes to gain one `Priority` level.
+    pub priority_aging: Duration,
+    /// Maximum number of requests sent per second.
+    pub rate_limit: usize,
+    /// Maximum number of requests sent per second to each kind of endpoint, within `rate_limit`.
+    /// No class is limited on its own by default.
+    pub endpoint_rate_limits: EndpointRateLimits,
+    /// Maximum number of new connections opened per second.
+    pub connection_rate_limit: usize,
+    /// Number of times a failed request is retried before giving up.
+    pub retry_attempts: usize,
+    /// Which failures are retried, and the backoff between retries.
+    pub retry: RetryConfig,
+    /// Fail fast for a while after repeated failures. Off by default.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Time allowed to establish a connection.
+    pub connect_timeout: Option<Duration>,
+    /// Time allowed to read the response body once the headers have arrived.
+    pub read_timeout: Option<Duration>,
+    /// Time allowed for a single HTTP exchange, from sending the request to the end of the body.
+    pub request_timeout: Option<Duration>,
+    /// Time allowed for a whole call, including the wait in the queue and every retry.
+    pub call_timeout: Option<Duration>,
+    /// How long an idle pooled connection is kept open.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Maximum number of idle connections kept per host.
+    pub pool_max_idle_per_host: usize,
+    /// TCP keep-alive interval for pooled connections.
+    pub tcp_keepalive: Option<Duration>,
+    /// Talk HTTP/2 straight away instead of starting with HTTP/1.1.
+    pub http2_prior_knowledge: bool,
+    /// Interval of HTTP/2 keep-alive pings.
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// Sent as `Accept-Datetime-Format`; OANDA uses RFC3339 when it is not set.
+    pub datetime_format: Option<DatetimeFormat>,
+    /// Send identical GETs that are in flight at the same time only once and share the response.
+    pub coalesce_requests: bool,
+    /// Record the HTTP traffic to a cassette file, or replay it from one.
+    pub cassette: CassetteMode,
+    /// Extra tower layers and where they go in the request stack.
+    pub layers: Vec<(StackPosition, UserLayer)>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            api_key: TokenSource::default(),
+            account_id: None,
+            environment: Environment::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            concurrency_limit: DEFAULT_CONCURRENCY_LIMIT,
+            priority_aging: DEFAULT_PRIORITY_AGING,
+            rate_limit: DEFAULT_RATE_LIMIT,
+            endpoint_rate_limits: EndpointRateLimits::default(),
+            connection_rate_limit: DEFAULT_CONNECTION_RATE_LIMIT,
+            retry_attempts: DEFAULT_RETRY_ATTEMPTS,
+            retry: RetryConfig::default(),
+            circuit_breaker: None,
+            coalesce_requests: false,
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: None,
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            call_timeout: None,
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            pool_max_idle_per_host: usize::MAX,
+            tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            http2_prior_knowledge: false,
+            http2_keep_alive_interval: None,
+            datetime_format: None,
+            cassette: CassetteMode::Live,
+            layers: Vec::new(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Check the values before any service is built, so a bad setting fails here
+    /// instead of panicking or deadlocking inside the service stack.
+    pub fn validate(&self) -> Result<(), APIError> {
+        // Other sources are checked when they are read, on `build`.
+        if let TokenSource::Static(token) = &self.api_key {
+            if token.is_empty() {
+                return Err(APIError::Config("api_key must not be empty".to_string()));
+            }
+        }
+        if let Some(account_id) = &self.account_id {
+            if account_id.trim().is_empty() {
+                return Err(APIError::Config("account_id must not be empty".to_string()));
+            }
+        }
+        if self.buffer_size == 0 {
+            return Err(APIError::Config("buffer_size must be greater than 0".to_string()));
+        }
+        if self.concurrency_limit == 0 {
+            return Err(APIError::Config("concurrency_limit must be greater than 0".to_string()));
+        }
+        if self.rate_limit == 0 {
+            return Err(APIError::Config("rate_limit must be greater than 0".to_string()));
+        }
+        for (class, rate_limit) in self.endpoint_rate_limits.iter() {
+            if rate_limit == 0 || rate_limit > self.rate_limit {
+                return Err(APIError::Config(format!(
+                    "{:?} rate limit must be between 1 and rate_limit ({}), got {}",
+                    class, self.rate_limit, rate_limit
+                )));
+            }
+        }
+        if self.connection_rate_limit == 0 {
+            return Err(APIError::Config("connection_rate_limit must be greater than 0".to_string()));
+        }
+        if !(self.retry.multiplier.is_finite() && self.retry.multiplier >= 1.0) {
+            return Err(APIError::Config("retry multiplier must be at least 1".to_string()));
+        }
+        if self.retry.max_delay < self.retry.base_delay {
+            return Err(APIError::Config("retry max_delay must not be less than base_delay".to_string()));
+        }
+        if let Some(breaker) = &self.circuit_breaker {
+            if breaker.failure_threshold == 0 || breaker.half_open_max_calls == 0 {
+                return Err(APIError::Config(
+                    "circuit breaker failure_threshold and half_open_max_calls must be greater than 0".to_string(),
+                ));
+            }
+        }
+        let is_oanda_host = !matches!(self.environment, Environment::Custom { .. });
+        if is_oanda_host && self.rate_limit > OANDA_MAX_REQUESTS_PER_SECOND {
+            return Err(APIError::Config(format!(
+                "rate_limit {} exceeds OANDA's limit of {} requests per second",
+                self.rate_limit, OANDA_MAX_REQUESTS_PER_SECOND
+            )));
+        }
+        for (name, timeout) in [
+            ("connect_timeout", self.connect_timeout),
+            ("read_timeout", self.read_timeout),
+            ("request_timeout", self.request_timeout),
+            ("call_timeout", self.call_timeout),
+        ] {
+            if timeout == Some(Duration::ZERO) {
+                return Err(APIError::Config(format!("{} must be greater than 0", name)));
+            }
+        }
+        if let Environment::Custom { rest_url, stream_url } = &self.environment {
+            for url in [rest_url, stream_url] {
+                reqwest::Url::parse(url)
+                    .map_err(|e| APIError::Config(format!("invalid url {}: {}", url, e)))?;
+            }
+        }
+        if let CassetteMode::Replay(path) = &self.cassette {
+            if !path.is_file() {
+                return Err(APIError::Config(format!("cassette {} does not exist", path.display())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the pooled `reqwest::Client` shared by every clone of the `OandaClient`.
+    pub fn http_client(&self) -> Result<Client, APIError> {
+        let mut builder = Client::builder()
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .tcp_keepalive(self.tcp_keepalive)
+            .http2_keep_alive_interval(self.http2_keep_alive_interval)
+            .dns_resolver(Arc::new(ConnectionLimiter::new(self.connection_rate_limit)));
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.request_timeout {
+            builder = builder.timeout(timeout);
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+
+        builder.build().map_err(APIError::from)
+    }
+}
+
+
+/// Builds an `OandaClient` with named settings.
+///
+/// ```no_run
+/// use oanda_rs::client::OandaClient;
+/// use oanda_rs::environment::Environment;
+///
+/// # fn main() -> Result<(), oanda_rs::error::APIError> {
+/// let client = OandaClient::builder()
+///     .api_key("my-token")
+///     .account_id("101-001-1234567-001")
+///     .environment(Environment::Practice)
+///     .rate_limit(50)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct OandaClientBuilder {
+    config: ClientConfig,
+}
+
+impl OandaClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: ClientConfig) -> Self {
+        OandaClientBuilder { config }
+    }
+
+    /// Read the configuration from environment variables (a `.env` file is loaded first if present).
+    ///
+    /// `OANDA_API_KEY`, or `OANDA_API_KEY_FILE` naming a file that holds the token, is required.
+    /// `OANDA_ACCOUNT_ID`, `OANDA_ENVIRONMENT` (`practice`, `live`
+    /// or `custom` together with `OANDA_REST_URL` and `OANDA_STREAM_URL`), `OANDA_BUFFER_SIZE`,
+    /// `OANDA_CONCURRENCY_LIMIT`, `OANDA_RATE_LIMIT` and `OANDA_RETRY_ATTEMPTS` are optional.
+    pub fn from_env() -> Result<Self, APIError> {
+        dotenv::dotenv().ok();
+
+        let api_key = match (env::var("OANDA_API_KEY"), env::var("OANDA_API_KEY_FILE")) {
+            (Ok(_), _) => TokenSource::Env("OANDA_API_KEY".to_string()),
+            (Err(_), Ok(path)) => TokenSource::File(PathBuf::from(path)),
+            _ => return Err(APIError::Config("OANDA_API_KEY or OANDA_API_KEY_FILE must be set".to_string())),
+        };
+
+        let mut config = ClientConfig {
+            api_key,
+            account_id: env::var("OANDA_ACCOUNT_ID").ok(),
+            ..ClientConfig::default()
+        };
+
+        if let Ok(environment) = env::var("OANDA_ENVIRONMENT") {
+            config.environment = match environment.to_lowercase().as_str() {
+                "custom" => Environment::Custom {
+                    rest_url: env::var("OANDA_REST_URL")
+                        .map_err(|_| APIError::Config("OANDA_REST_URL must be set for a custom environment".to_string()))?,
+                    stream_url: env::var("OANDA_STREAM_URL")
+                        .map_err(|_| APIError::Config("OANDA_STREAM_URL must be set for a custom environment".to_string()))?,
+                },
+                _ => environment.parse()?,
+            };
+        }
+
+        config.buffer_size = env_or("OANDA_BUFFER_SIZE", config.buffer_size)?;
+        config.concurrency_limit = env_or("OANDA_CONCURRENCY_LIMIT", config.concurrency_limit)?;
+        config.rate_limit = env_or("OANDA_RATE_LIMIT", config.rate_limit)?;
+        config.retry_attempts = env_or("OANDA_RETRY_ATTEMPTS", config.retry_attempts)?;
+
+        Ok(OandaClientBuilder { config })
+    }
+
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.config.api_key = TokenSource::Static(ApiToken::new(api_key));
+        self
+    }
+
+    /// Read the token from the environment variable `var`.
+    pub fn api_key_from_env(mut self, var: &str) -> Self {
+        self.config.api_key = TokenSource::Env(var.to_string());
+        self
+    }
+
+    /// Read the token from a file, such as a mounted secret. Surrounding whitespace is ignored.
+    pub fn api_key_from_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.api_key = TokenSource::File(path.into());
+        self
+    }
+
+    /// Fetch the token with `f`, on `build` and on every `OandaClient::reload_api_key`.
+    pub fn api_key_from_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> Result<ApiToken, APIError> + Send + Sync + 'static,
+    {
+        self.config.api_key = TokenSource::callback(f);
+        self
+    }
+
+    pub fn account_id(mut self, account_id: &str) -> Self {
+        self.config.account_id = Some(account_id.to_string());
+        self
+    }
+
+    pub fn environment(mut self, environment: Environment) -> Self {
+        self.config.environment = environment;
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.config.buffer_size = buffer_size;
+        self
+    }
+
+    pub fn concurrency_limit(mut self, concurrency_limit: usize) -> Self {
+        self.config.concurrency_limit = concurrency_limit;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: usize) -> Self {
+        self.config.rate_limit = rate_limit;
+        self
+    }
+
+    /// Limit the requests sent per second to one kind of endpoint, such as candle downloads.
+    pub fn endpoint_rate_limit(mut self, class: EndpointClass, rate_limit: usize) -> Self {
+        self.config.endpoint_rate_limits.set(class, rate_limit);
+        self
+    }
+
+    pub fn priority_aging(mut self, aging: Duration) -> Self {
+        self.config.priority_aging = aging;
+        self
+    }
+
+    pub fn connection_rate_limit(mut self, rate_limit: usize) -> Self {
+        self.config.connection_rate_limit = rate_limit;
+        self
+    }
+
+    pub fn retry_attempts(mut self, retry_attempts: usize) -> Self {
+        self.config.retry_attempts = retry_attempts;
+        self
+    }
+
+    /// Replace the rules deciding which failed requests are sent again.
+    pub fn retry_classifier(mut self, classifier: RetryClassifier) -> Self {
+        self.config.retry.classifier = classifier;
+        self
+    }
+
+    /// Stop sending requests for `cool_down` after `failure_threshold` failures in a row, failing
+    /// them with `APIError::CircuitOpen` instead. See `OandaClient::circuit_breaker` for its events.
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.config.circuit_breaker = Some(config);
+        self
+    }
+
+    /// Share one response among identical GETs in flight at the same time, so that components
+    /// asking for the same summary together use up the rate limit once. Off by default.
+    pub fn coalesce_requests(mut self, enabled: bool) -> Self {
+        self.config.coalesce_requests = enabled;
+        self
+    }
+
+    /// Replace the retry backoff and classification. The number of retries is set with `retry_attempts`.
+    pub fn retry_config(mut self, retry: RetryConfig) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout = Some(timeout);
+        self
+    }
+
+    pub fn call_timeout(mut self, timeout: Duration) -> Self {
+        self.config.call_timeout = Some(timeout);
+        self
+    }
+
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.pool_idle_timeout = timeout;
+        self
+    }
+
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.config.pool_max_idle_per_host = max;
+        self
+    }
+
+    pub fn tcp_keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.config.tcp_keepalive = interval;
+        self
+    }
+
+    pub fn http2_prior_knowledge(mut self, enabled: bool) -> Self {
+        self.config.http2_prior_knowledge = enabled;
+        self
+    }
+
+    pub fn http2_keep_alive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.http2_keep_alive_interval = interval;
+        self
+    }
+
+    pub fn datetime_format(mut self, format: DatetimeFormat) -> Self {
+        self.config.datetime_format = Some(format);
+        self
+    }
+
+    /// Write every request/response pair to the cassette at `path`, with the API key redacted.
+    pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.cassette = CassetteMode::Record(path.into());
+        self
+    }
+
+    /// Answer requests from the cassette at `path` instead of the network.
+    pub fn replay_from(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.cassette = CassetteMode::Replay(path.into());
+        self
+    }
+
+    /// Add a tower layer at `position` in the request stack, such as request signing or
+    /// fault injection. Layers added for the same position run in the order they were added.
+    pub fn layer<L>(mut self, position: StackPosition, layer: L) -> Self
+    where
+        L: Layer<BoxRequestService> + Send + Sync + 'static,
+        L::Service: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
+        <L::Service as Service<ClonableRequest>>::Error: Into<BoxError>,
+        <L::Service as Service<ClonableRequest>>::Future: Send + 'static,
+    {
+        self.config.layers.push((position, UserLayer::new(layer)));
+        self
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Validate the configuration and build the client.
+    /// Must be called from within a tokio runtime.
+    pub fn build(self) -> Result<OandaClient, APIError> {
+        self.config.validate()?;
+        OandaClient::from_config(self.config)
+    }
+}
+
+
+fn env_or<T: FromStr>(key: &str, default: T) -> Result<T, APIError> {
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| APIError::Config(format!("{} has an invalid value: {}", key, value))),
+        Err(_) => Ok(default),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use super::*;
+    use crate::mock::{fixtures, MockResponse, MockServer};
+
+    #[test]
+    fn test_validate_rejects_bad_values() {
+        let config = ClientConfig::default();
+        assert!(matches!(config.validate(), Err(APIError::Config(_))));
+
+        let config = ClientConfig { api_key: TokenSource::Static("token".into()), ..ClientConfig::default() };
+        assert!(config.validate().is_ok());
+
+        for config in [
+            ClientConfig { buffer_size: 0, ..config.clone() },
+            ClientConfig { concurrency_limit: 0, ..config.clone() },
+            ClientConfig { rate_limit: 0, ..config.clone() },
+            ClientConfig { rate_limit: 500, ..config.clone() },
+            ClientConfig { connection_rate_limit: 0, ..config.clone() },
+            ClientConfig { endpoint_rate_limits: EndpointRateLimits { data: Some(0), ..Default::default() }, ..config.clone() },
+            ClientConfig { endpoint_rate_limits: EndpointRateLimits { trading: Some(101), ..Default::default() }, ..config.clone() },
+            ClientConfig { account_id: Some(" ".to_string()), ..config.clone() },
+            ClientConfig { connect_timeout: Some(Duration::ZERO), ..config.clone() },
+            ClientConfig { read_timeout: Some(Duration::ZERO), ..config.clone() },
+            ClientConfig { request_timeout: Some(Duration::ZERO), ..config.clone() },
+            ClientConfig { call_timeout: Some(Duration::ZERO), ..config.clone() },
+            ClientConfig { retry: RetryConfig { multiplier: 0.5, ..RetryConfig::default() }, ..config.clone() },
+            ClientConfig { retry: RetryConfig { max_delay: Duration::ZERO, ..RetryConfig::default() }, ..config.clone() },
+        ] {
+            assert!(matches!(config.validate(), Err(APIError::Config(_))));
+        }
+
+        let mut limits = EndpointRateLimits::default();
+        limits.set(EndpointClass::Data, config.rate_limit);
+        assert!(ClientConfig { endpoint_rate_limits: limits, ..config.clone() }.validate().is_ok());
+
+        let custom = ClientConfig {
+            rate_limit: 500,
+            environment: Environment::Custom {
+                rest_url: "http://127.0.0.1:8080".to_string(),
+                stream_url: "http://127.0.0.1:8081".to_string(),
+            },
+            ..config
+        };
+        assert!(custom.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_builder_defaults() {
+        let client = OandaClientBuilder::new()
+            .api_key("token")
+            .account_id("101-001-1234567-001")
+            .build()
+            .unwrap();
+        assert_eq!(client.get_account_id().unwrap(), "101-001-1234567-001");
+        assert_eq!(client.get_environment(), &Environment::Practice);
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout_fires_on_stalled_body() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::ok(fixtures::account_summary()).delay_body(Duration::from_secs(5)));
+        let mut client = server
+            .client_builder()
+            .read_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let error = client.get_account_summary().await.unwrap_err();
+        assert!(matches!(error, APIError::Timeout(t) if t == Duration::from_millis(100)), "{:?}", error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_fires_on_delayed_response() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::ok(fixtures::account_summary()).delay(Duration::from_secs(5)));
+        let mut client = server
+            .client_builder()
+            .retry_attempts(0)
+            .request_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let error = client.get_account_summary().await.unwrap_err();
+        assert!(error.is_timeout(), "{:?}", error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
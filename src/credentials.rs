@@ -0,0 +1,285 @@
+//! The API token and where it comes from.
+//!
+//! The token never shows up in `Debug` or `Display` output and its memory is zeroed when it is
+//! dropped. A client and all of its clones share one `Credentials`, so a token rotated on any
+//! of them is used by the next request sent through every one:
+//!
+//! ```no_run
+//! use oanda_rs::client::OandaClient;
+//!
+//! # fn main() -> Result<(), oanda_rs::error::APIError> {
+//! let client = OandaClient::builder()
+//!     .api_key_from_file("/run/secrets/oanda-token")
+//!     .account_id("101-001-1234567-001")
+//!     .build()?;
+//!
+//! // After the secret was replaced on disk:
+//! client.reload_api_key()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use reqwest::header::HeaderValue;
+use zeroize::Zeroizing;
+
+use crate::error::APIError;
+
+
+/// Stands in for the token wherever it would otherwise be printed or written.
+pub const REDACTED: &str = "[REDACTED]";
+
+
+/// An OANDA personal access token. Redacted when formatted, zeroed on drop.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ApiToken(Zeroizing<String>);
+
+impl ApiToken {
+    /// Surrounding whitespace, such as the newline at the end of a secret file, is dropped.
+    pub fn new(token: impl Into<String>) -> ApiToken {
+        let token = Zeroizing::new(token.into());
+        ApiToken(Zeroizing::new(token.trim().to_string()))
+    }
+
+    /// The token itself, for the `Authorization` header.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// An `Authorization` header value, marked sensitive so that HTTP libraries leave it out
+    /// of their own logs.
+    pub(crate) fn authorization(&self) -> Result<HeaderValue, APIError> {
+        let header = Zeroizing::new(format!("Bearer {}", self.expose_secret()));
+        let mut value = HeaderValue::from_str(&header)
+            .map_err(|_| APIError::Config("api_key contains characters not allowed in a header".to_string()))?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+impl From<&str> for ApiToken {
+    fn from(token: &str) -> Self {
+        ApiToken::new(token)
+    }
+}
+
+impl From<String> for ApiToken {
+    fn from(token: String) -> Self {
+        ApiToken::new(token)
+    }
+}
+
+impl fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiToken({})", REDACTED)
+    }
+}
+
+impl fmt::Display for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+
+/// Where the token is read from, on `build` and again on every `OandaClient::reload_api_key`.
+#[derive(Clone)]
+pub enum TokenSource {
+    /// A token given directly.
+    Static(ApiToken),
+    /// The environment variable with this name.
+    Env(String),
+    /// The contents of this file, such as a mounted secret.
+    File(PathBuf),
+    /// A function that fetches the token, for example from a vault.
+    Callback(Arc<dyn Fn() -> Result<ApiToken, APIError> + Send + Sync>),
+}
+
+impl TokenSource {
+    pub fn callback<F>(f: F) -> TokenSource
+    where
+        F: Fn() -> Result<ApiToken, APIError> + Send + Sync + 'static,
+    {
+        TokenSource::Callback(Arc::new(f))
+    }
+
+    /// Read the token. An empty token is an error.
+    pub fn load(&self) -> Result<ApiToken, APIError> {
+        let token = match self {
+            TokenSource::Static(token) => token.clone(),
+            TokenSource::Env(var) => std::env::var(var)
+                .map(ApiToken::new)
+                .map_err(|_| APIError::Config(format!("{} must be set", var)))?,
+            TokenSource::File(path) => std::fs::read_to_string(path)
+                .map(ApiToken::new)
+                .map_err(|e| APIError::Config(format!("cannot read api_key from {}: {}", path.display(), e)))?,
+            TokenSource::Callback(f) => f()?,
+        };
+        if token.is_empty() {
+            return Err(APIError::Config("api_key must not be empty".to_string()));
+        }
+        Ok(token)
+    }
+}
+
+impl Default for TokenSource {
+    fn default() -> Self {
+        TokenSource::Static(ApiToken::default())
+    }
+}
+
+impl fmt::Debug for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenSource::Static(token) => f.debug_tuple("Static").field(token).finish(),
+            TokenSource::Env(var) => f.debug_tuple("Env").field(var).finish(),
+            TokenSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            TokenSource::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+
+/// The token in use by a client and its clones, together with the source it came from.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    token: Arc<RwLock<ApiToken>>,
+    source: TokenSource,
+}
+
+impl Credentials {
+    pub fn load(source: TokenSource) -> Result<Credentials, APIError> {
+        Ok(Credentials {
+            token: Arc::new(RwLock::new(source.load()?)),
+            source,
+        })
+    }
+
+    /// A copy of the current token.
+    pub fn token(&self) -> ApiToken {
+        self.token.read().unwrap().clone()
+    }
+
+    /// Use `token` for every request sent from now on. Requests already sent keep the old one.
+    pub fn rotate(&self, token: ApiToken) -> Result<(), APIError> {
+        if token.is_empty() {
+            return Err(APIError::Config("api_key must not be empty".to_string()));
+        }
+        // Checked before the swap, so a bad token never replaces a working one.
+        token.authorization()?;
+        *self.token.write().unwrap() = token;
+        Ok(())
+    }
+
+    /// Read the token from its source again and rotate to it.
+    pub fn reload(&self) -> Result<(), APIError> {
+        self.rotate(self.source.load()?)
+    }
+
+    pub(crate) fn authorization(&self) -> Result<HeaderValue, APIError> {
+        self.token.read().unwrap().authorization()
+    }
+
+    /// Replace the current token wherever it appears in `text`.
+    pub(crate) fn redact(&self, text: &str) -> String {
+        let token = self.token.read().unwrap();
+        if token.is_empty() {
+            text.to_string()
+        } else {
+            text.replace(token.expose_secret(), REDACTED)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use super::*;
+    use crate::mock::{MockServer, MOCK_API_KEY};
+
+    #[test]
+    fn test_token_is_redacted() {
+        let token = ApiToken::new("secret-token\n");
+        assert_eq!(token.expose_secret(), "secret-token");
+        assert_eq!(format!("{}", token), REDACTED);
+        assert!(!format!("{:?}", token).contains("secret"));
+        assert!(!format!("{:?}", TokenSource::Static(token.clone())).contains("secret"));
+
+        let credentials = Credentials::load(TokenSource::Static(token)).unwrap();
+        assert!(!format!("{:?}", credentials).contains("secret"));
+        assert!(credentials.authorization().unwrap().is_sensitive());
+    }
+
+    #[test]
+    fn test_token_sources() {
+        let path = std::env::temp_dir().join(format!("oanda_rs-token-{}", std::process::id()));
+        std::fs::write(&path, "file-token\n").unwrap();
+        assert_eq!(TokenSource::File(path.clone()).load().unwrap().expose_secret(), "file-token");
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(TokenSource::File(path).load(), Err(APIError::Config(_))));
+
+        std::env::set_var("OANDA_RS_TEST_TOKEN_SOURCE", "env-token");
+        assert_eq!(TokenSource::Env("OANDA_RS_TEST_TOKEN_SOURCE".to_string()).load().unwrap().expose_secret(), "env-token");
+        assert!(TokenSource::Env("OANDA_RS_TEST_TOKEN_UNSET".to_string()).load().is_err());
+
+        let source = TokenSource::callback(|| Ok(ApiToken::new("callback-token")));
+        assert_eq!(source.load().unwrap().expose_secret(), "callback-token");
+        assert!(TokenSource::callback(|| Ok(ApiToken::new(" "))).load().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rotation_reaches_clones() {
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
+        let mut clone = client.clone();
+
+        client.get_accounts().await.unwrap();
+        clone.rotate_api_key("rotated-token").unwrap();
+        client.get_accounts().await.unwrap();
+        clone.get_accounts().await.unwrap();
+
+        let requests = server.received_requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].header("Authorization"), Some(format!("Bearer {}", MOCK_API_KEY).as_str()));
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer rotated-token"));
+        assert_eq!(requests[2].header("Authorization"), Some("Bearer rotated-token"));
+        assert_eq!(server.hits(Method::GET, "/v3/accounts"), 3);
+
+        assert!(client.rotate_api_key("").is_err());
+        assert!(client.rotate_api_key("bad\ntoken").is_err());
+        client.get_accounts().await.unwrap();
+        assert_eq!(server.received_requests()[3].header("Authorization"), Some("Bearer rotated-token"));
+    }
+
+    #[tokio::test]
+    async fn test_reload_from_callback() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = MockServer::start().await.with_fixtures();
+        let version = Arc::new(AtomicUsize::new(1));
+        let current = version.clone();
+        let mut client = server
+            .client_builder()
+            .api_key_from_fn(move || Ok(ApiToken::new(format!("token-v{}", current.load(Ordering::SeqCst)))))
+            .build()
+            .unwrap();
+
+        client.get_accounts().await.unwrap();
+        version.store(2, Ordering::SeqCst);
+        client.reload_api_key().unwrap();
+        client.get_accounts().await.unwrap();
+
+        let requests = server.received_requests();
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer token-v1"));
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer token-v2"));
+    }
+}
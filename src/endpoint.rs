@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::client::OandaClient;
+use crate::error::APIError;
+use crate::response::Response;
+
+
+/// Placeholder in a path template that `OandaClient::execute` replaces with the client's account ID.
+pub const ACCOUNT_ID: &str = "{accountID}";
+
+/// Describes one v20 API call: how to build the request and what it decodes into.
+///
+/// Every endpoint in the crate is an `Endpoint`, and endpoints the crate has not wrapped yet
+/// can be described the same way and sent with `OandaClient::execute`:
+///
+/// ```no_run
+/// use oanda_rs::endpoint::Endpoint;
+/// use serde_json::Value;
+///
+/// struct GetOpenTrades;
+///
+/// impl Endpoint for GetOpenTrades {
+///     type Response = Value;
+///
+///     fn path(&self) -> String {
+///         "/v3/accounts/{accountID}/openTrades".to_string()
+///     }
+/// }
+/// ```
+pub trait Endpoint {
+    type Response: DeserializeOwned;
+
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    /// Path on the REST host. `{accountID}` is filled in from the client.
+    fn path(&self) -> String;
+
+    fn query(&self) -> Option<HashMap<String, String>> {
+        None
+    }
+
+    fn body(&self) -> Option<Value> {
+        None
+    }
+}
+
+
+/// Replace the `{accountID}` placeholder, failing when the path needs an account and none is set.
+pub fn resolve_path(template: &str, account_id: Option<&str>) -> Result<String, APIError> {
+    if !template.contains(ACCOUNT_ID) {
+        return Ok(template.to_string());
+    }
+    match account_id {
+        Some(account_id) => Ok(template.replace(ACCOUNT_ID, account_id)),
+        None => Err(APIError::Other("Account ID Not Set".to_string())),
+    }
+}
+
+
+impl OandaClient {
+    /// Send any `Endpoint` through the client's rate-limited pipeline and decode its response.
+    pub async fn execute<E: Endpoint>(&mut self, endpoint: &E) -> Result<E::Response, APIError> {
+        self.execute_with_response(endpoint).await.map(Response::into_inner)
+    }
+
+    /// Like `execute`, but keeps the status and headers, including the `RequestID`.
+    pub async fn execute_with_response<E: Endpoint>(&mut self, endpoint: &E) -> Result<Response<E::Response>, APIError> {
+        let path = resolve_path(&endpoint.path(), self.get_account_id().map(|s| s.as_str()))?;
+        let query = endpoint.query();
+        let body = endpoint.body();
+        self.request_with_response(endpoint.method(), &path, query.as_ref(), body.as_ref()).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_path() {
+        assert_eq!(resolve_path("/v3/accounts", None).unwrap(), "/v3/accounts");
+        assert_eq!(
+            resolve_path("/v3/accounts/{accountID}/summary", Some("101-001-1234567-001")).unwrap(),
+            "/v3/accounts/101-001-1234567-001/summary"
+        );
+        assert!(resolve_path("/v3/accounts/{accountID}/summary", None).is_err());
+    }
+}
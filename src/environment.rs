@@ -0,0 +1,75 @@
+use std::str::FromStr;
+
+use crate::error::APIError;
+
+
+/// The OANDA v20 environment a client talks to.
+///
+/// `Practice` and `Live` point at OANDA's published hosts, `Custom` lets the
+/// same code run against a local stand-in or a proxy.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Environment {
+    #[default]
+    Practice,
+    Live,
+    Custom {
+        rest_url: String,
+        stream_url: String,
+    },
+}
+
+impl Environment {
+    /// Base URL for the REST endpoints, without a trailing slash.
+    pub fn rest_url(&self) -> &str {
+        match self {
+            Environment::Practice => "https://api-fxpractice.oanda.com",
+            Environment::Live => "https://api-fxtrade.oanda.com",
+            Environment::Custom { rest_url, .. } => rest_url.trim_end_matches('/'),
+        }
+    }
+
+    /// Base URL for the streaming endpoints, without a trailing slash.
+    pub fn stream_url(&self) -> &str {
+        match self {
+            Environment::Practice => "https://stream-fxpractice.oanda.com",
+            Environment::Live => "https://stream-fxtrade.oanda.com",
+            Environment::Custom { stream_url, .. } => stream_url.trim_end_matches('/'),
+        }
+    }
+}
+
+impl FromStr for Environment {
+    type Err = APIError;
+
+    /// Parse `practice` or `live`. A custom environment needs its URLs and cannot be parsed from a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "practice" => Ok(Environment::Practice),
+            "live" => Ok(Environment::Live),
+            _ => Err(APIError::Config(format!("Unknown environment: {}", s))),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_urls() {
+        assert_eq!(Environment::default(), Environment::Practice);
+        assert_eq!(Environment::Live.rest_url(), "https://api-fxtrade.oanda.com");
+        assert_eq!(Environment::Live.stream_url(), "https://stream-fxtrade.oanda.com");
+
+        let custom = Environment::Custom {
+            rest_url: "http://127.0.0.1:8080/".to_string(),
+            stream_url: "http://127.0.0.1:8081".to_string(),
+        };
+        assert_eq!(custom.rest_url(), "http://127.0.0.1:8080");
+        assert_eq!(custom.stream_url(), "http://127.0.0.1:8081");
+
+        assert_eq!("LIVE".parse::<Environment>().unwrap(), Environment::Live);
+        assert!("staging".parse::<Environment>().is_err());
+    }
+}
@@ -1,6 +1,15 @@
+use std::collections::HashMap;
 use std::error::Error as StdError;
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use thiserror::Error as ErrorMacro;
 
+use crate::primitives::datetime::DateTime;
+
 
 #[derive(Debug, ErrorMacro)]
 pub enum APIError {
@@ -12,11 +21,277 @@ pub enum APIError {
     Other(String),
     #[error("Clone error: {0}")]
     Clone(String),
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// The call, or reading its response body, ran out of the time it was given.
+    #[error("Timed out after {0:?}")]
+    Timeout(Duration),
+    /// The circuit breaker is open after repeated failures; the request was not sent.
+    /// Carries how long it stays open.
+    #[error("Circuit open, not sending requests for another {0:?}")]
+    CircuitOpen(Duration),
+    #[error("Bad request: {0}")]
+    BadRequest(Box<ErrorResponse>),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(Box<ErrorResponse>),
+    #[error("Forbidden: {0}")]
+    Forbidden(Box<ErrorResponse>),
+    #[error("Not found: {0}")]
+    NotFound(Box<ErrorResponse>),
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(Box<ErrorResponse>),
+    #[error("Rate limited: {0}")]
+    RateLimited(Box<ErrorResponse>),
+    #[error("Server error: {0}")]
+    Server(Box<ErrorResponse>),
+    #[error("Unexpected response: {0}")]
+    UnexpectedStatus(Box<ErrorResponse>),
+}
+
+impl APIError {
+    /// Build the error matching a non-success HTTP status from the response body OANDA sent.
+    pub fn from_status(status: StatusCode, body: &[u8]) -> APIError {
+        let response = Box::new(ErrorResponse::from_body(status, body));
+        match status.as_u16() {
+            400 => APIError::BadRequest(response),
+            401 => APIError::Unauthorized(response),
+            403 => APIError::Forbidden(response),
+            404 => APIError::NotFound(response),
+            405 => APIError::MethodNotAllowed(response),
+            429 => APIError::RateLimited(response),
+            500..=599 => APIError::Server(response),
+            _ => APIError::UnexpectedStatus(response),
+        }
+    }
+
+    /// The OANDA error details, if this error came from an HTTP error response.
+    pub fn error_response(&self) -> Option<&ErrorResponse> {
+        match self {
+            APIError::BadRequest(response)
+            | APIError::Unauthorized(response)
+            | APIError::Forbidden(response)
+            | APIError::NotFound(response)
+            | APIError::MethodNotAllowed(response)
+            | APIError::RateLimited(response)
+            | APIError::Server(response)
+            | APIError::UnexpectedStatus(response) => Some(response),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn error_response_mut(&mut self) -> Option<&mut ErrorResponse> {
+        match self {
+            APIError::BadRequest(response)
+            | APIError::Unauthorized(response)
+            | APIError::Forbidden(response)
+            | APIError::NotFound(response)
+            | APIError::MethodNotAllowed(response)
+            | APIError::RateLimited(response)
+            | APIError::Server(response)
+            | APIError::UnexpectedStatus(response) => Some(response),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            APIError::HTTP(e) => e.status(),
+            _ => self.error_response().and_then(|r| StatusCode::from_u16(r.status).ok()),
+        }
+    }
+
+    /// True for `Timeout` and for HTTP errors reqwest reports as timeouts.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            APIError::Timeout(_) => true,
+            APIError::HTTP(e) => e.is_timeout(),
+            _ => false,
+        }
+    }
+
+    pub fn reject_transaction(&self) -> Option<&RejectTransaction> {
+        self.error_response().and_then(|r| r.reject_transaction.as_ref())
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.error_response().and_then(|r| r.request_id.as_deref())
+    }
+}
+
+
+/// The body OANDA sends with a non-success status, together with that status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub status: u16,
+    pub error_code: Option<String>,
+    pub error_message: String,
+    pub last_transaction_id: Option<String>,
+    pub related_transaction_ids: Vec<String>,
+    pub reject_transaction: Option<RejectTransaction>,
+    /// The `RequestID` header of the response, to quote when reporting the problem to OANDA.
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct ErrorBody {
+    errorCode: Option<String>,
+    errorMessage: Option<String>,
+    lastTransactionID: Option<String>,
+    #[serde(default)]
+    relatedTransactionIDs: Vec<String>,
+}
+
+impl ErrorResponse {
+    /// Parse an error body. Bodies that are not OANDA JSON (a proxy's HTML page, for example)
+    /// are kept as the error message.
+    pub fn from_body(status: StatusCode, body: &[u8]) -> ErrorResponse {
+        let value: Option<Value> = serde_json::from_slice(body).ok();
+        let parsed = value
+            .as_ref()
+            .and_then(|v| serde_json::from_value::<ErrorBody>(v.clone()).ok());
+
+        let reject_transaction = value.as_ref().and_then(RejectTransaction::find);
+
+        match parsed {
+            Some(parsed) => ErrorResponse {
+                status: status.as_u16(),
+                error_code: parsed.errorCode,
+                error_message: parsed
+                    .errorMessage
+                    .unwrap_or_else(|| status.canonical_reason().unwrap_or_default().to_string()),
+                last_transaction_id: parsed.lastTransactionID,
+                related_transaction_ids: parsed.relatedTransactionIDs,
+                reject_transaction,
+                request_id: None,
+            },
+            None => ErrorResponse {
+                status: status.as_u16(),
+                error_code: None,
+                error_message: String::from_utf8_lossy(body).trim().to_string(),
+                last_transaction_id: None,
+                related_transaction_ids: Vec::new(),
+                reject_transaction: None,
+                request_id: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.error_message)?;
+        if let Some(code) = &self.error_code {
+            write!(f, " (errorCode {})", code)?;
+        }
+        if let Some(reject) = &self.reject_transaction {
+            write!(f, " [{}", reject.r#type)?;
+            if let Some(reason) = &reject.rejectReason {
+                write!(f, ": {}", reason)?;
+            }
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// A `*RejectTransaction` OANDA attaches to an error response, such as
+/// `orderRejectTransaction` or `clientConfigureRejectTransaction`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct RejectTransaction {
+    /// The body field the transaction was found in, e.g. `orderRejectTransaction`.
+    #[serde(skip)]
+    pub field: String,
+    pub id: Option<String>,
+    #[serde(default, with = "crate::primitives::datetime::option")]
+    pub time: Option<DateTime>,
+    pub accountID: Option<String>,
+    pub batchID: Option<String>,
+    pub requestID: Option<String>,
+    pub userID: Option<u64>,
+    pub r#type: String,
+    pub rejectReason: Option<String>,
+    /// The fields specific to the transaction type.
+    #[serde(flatten)]
+    pub details: HashMap<String, Value>,
+}
+
+impl RejectTransaction {
+    fn find(body: &Value) -> Option<RejectTransaction> {
+        body.as_object()?
+            .iter()
+            .find(|(key, _)| key.ends_with("RejectTransaction"))
+            .and_then(|(key, value)| {
+                let mut transaction: RejectTransaction = serde_json::from_value(value.clone()).ok()?;
+                transaction.field = key.clone();
+                Some(transaction)
+            })
+    }
 }
 
 
 impl From<Box<dyn StdError + Send + Sync>> for APIError {
     fn from(error: Box<dyn StdError + Send + Sync>) -> Self {
-        APIError::Other(error.to_string())
+        match error.downcast::<APIError>() {
+            Ok(error) => *error,
+            Err(error) => match error.downcast::<reqwest::Error>() {
+                Ok(error) => APIError::HTTP(*error),
+                Err(error) => APIError::Other(error.to_string()),
+            },
+        }
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_status() {
+        let body = br#"{
+            "errorCode": "MARKET_ORDER_REJECT",
+            "errorMessage": "Insufficient margin",
+            "lastTransactionID": "6368",
+            "relatedTransactionIDs": ["6368"],
+            "orderRejectTransaction": {
+                "id": "6368",
+                "time": "2024-08-31T17:58:17.000000000Z",
+                "accountID": "101-001-1234567-001",
+                "batchID": "6368",
+                "userID": 1234567,
+                "type": "MARKET_ORDER_REJECT",
+                "instrument": "EUR_USD",
+                "units": "100000",
+                "rejectReason": "INSUFFICIENT_MARGIN"
+            }
+        }"#;
+
+        let error = APIError::from_status(StatusCode::BAD_REQUEST, body);
+        assert!(matches!(error, APIError::BadRequest(_)));
+        assert_eq!(error.status(), Some(StatusCode::BAD_REQUEST));
+
+        let response = error.error_response().unwrap();
+        assert_eq!(response.error_code.as_deref(), Some("MARKET_ORDER_REJECT"));
+        assert_eq!(response.last_transaction_id.as_deref(), Some("6368"));
+
+        let reject = error.reject_transaction().unwrap();
+        assert_eq!(reject.field, "orderRejectTransaction");
+        assert_eq!(reject.rejectReason.as_deref(), Some("INSUFFICIENT_MARGIN"));
+        assert_eq!(reject.details["instrument"], "EUR_USD");
+    }
+
+    #[test]
+    fn test_from_status_without_json() {
+        let error = APIError::from_status(StatusCode::BAD_GATEWAY, b"<html>Bad Gateway</html>");
+        assert!(matches!(error, APIError::Server(_)));
+        assert_eq!(error.error_response().unwrap().error_message, "<html>Bad Gateway</html>");
+
+        let error = APIError::from_status(StatusCode::TOO_MANY_REQUESTS, br#"{"errorMessage":"Rate limit violation"}"#);
+        assert!(matches!(error, APIError::RateLimited(_)));
+        assert_eq!(error.error_response().unwrap().error_message, "Rate limit violation");
+    }
+}
@@ -1,6 +1,10 @@
 use std::collections::HashMap;
 use crate::client::OandaClient;
+use crate::endpoint::Endpoint;
 use crate::error::APIError;
+use crate::response::Response;
+use crate::primitives::datetime::{DateTime, DatetimeFormat};
+use crate::primitives::decimal::PriceValue;
 use serde::{Serialize, Deserialize};
 use crate::error::APIError as Err;
 
@@ -32,6 +36,7 @@ pub enum Granularity {
 
 
 impl Granularity {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Granularity, Err> {
         match s {
             "S5" => Ok(Granularity::S5),
@@ -61,6 +66,7 @@ impl Granularity {
 }
 
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for Granularity {
     fn to_string(&self) -> String {
         match self {
@@ -104,25 +110,26 @@ pub struct CandlesResponse {
 pub struct Candle {
     pub complete: bool,
     pub mid: Mid,
-    pub time: String,
+    #[serde(with = "crate::primitives::datetime")]
+    pub time: DateTime,
     pub volume: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[allow(dead_code)]
 pub struct Mid {
-    pub c: String,
-    pub h: String,
-    pub l: String,
-    pub o: String,
+    pub c: PriceValue,
+    pub h: PriceValue,
+    pub l: PriceValue,
+    pub o: PriceValue,
 }
 
 
 #[derive(Debug, Clone)]
 pub enum CandleQueryParam {
     Count(i32),
-    From(String),
-    To(String),
+    From(DateTime),
+    To(DateTime),
     Granularity(Granularity),
     Price(String),
     Smooth(bool),
@@ -133,12 +140,13 @@ pub enum CandleQueryParam {
 }
 
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for CandleQueryParam {
     fn to_string(&self) -> String {
         match self {
             CandleQueryParam::Count(v) => v.to_string(),
-            CandleQueryParam::From(v) => v.clone(),
-            CandleQueryParam::To(v) => v.clone(),
+            CandleQueryParam::From(v) => DatetimeFormat::RFC3339.format(v),
+            CandleQueryParam::To(v) => DatetimeFormat::RFC3339.format(v),
             CandleQueryParam::Granularity(v) => v.to_string(),
             CandleQueryParam::Price(v) => v.clone(),
             CandleQueryParam::Smooth(v) => v.to_string(),
@@ -151,9 +159,12 @@ impl ToString for CandleQueryParam {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CandleQuery {
     parameters: HashMap<String, String>,
+    /// `From` and `To`, formatted when the query is built.
+    times: HashMap<String, DateTime>,
+    datetime_format: Option<DatetimeFormat>,
 }
 
 
@@ -161,40 +172,101 @@ impl CandleQuery {
     pub fn new() -> Self {
         Self {
             parameters: HashMap::new(),
+            times: HashMap::new(),
+            datetime_format: None,
         }
     }
 
+    /// Format `From` and `To` in this format instead of the client's `Accept-Datetime-Format`.
+    /// OANDA reads request DateTimes in the format the client asks for, so this is rarely needed.
+    pub fn datetime_format(&mut self, format: DatetimeFormat) -> &mut Self {
+        self.datetime_format = Some(format);
+        self
+    }
+
     pub fn add_param(&mut self, key: &str, value: CandleQueryParam) -> &mut Self {
-        self.parameters.insert(key.to_string(), value.to_string());
+        match value {
+            CandleQueryParam::From(v) | CandleQueryParam::To(v) => {
+                self.parameters.remove(key);
+                self.times.insert(key.to_string(), v);
+            }
+            _ => {
+                self.times.remove(key);
+                self.parameters.insert(key.to_string(), value.to_string());
+            }
+        }
         self
     }
 
+    /// The parameters, with `From` and `To` in the query's `datetime_format`, or RFC3339 if it
+    /// has none. Pass the `CandleQuery` itself to `get_candles` to use the client's format.
     pub fn build(&self) -> HashMap<String, String> {
-        self.parameters.clone()
+        self.build_for(DatetimeFormat::RFC3339)
+    }
+
+    /// The parameters, with `From` and `To` in the query's `datetime_format`, or in `format` if
+    /// it has none.
+    pub fn build_for(&self, format: DatetimeFormat) -> HashMap<String, String> {
+        let format = self.datetime_format.unwrap_or(format);
+        let mut parameters = self.parameters.clone();
+        parameters.extend(self.times.iter().map(|(key, time)| (key.clone(), format.format(time))));
+        parameters
+    }
+}
+
+/// Parameters that are already formatted, sent as given.
+impl From<HashMap<String, String>> for CandleQuery {
+    fn from(parameters: HashMap<String, String>) -> Self {
+        CandleQuery { parameters, ..CandleQuery::new() }
+    }
+}
+
+
+/// `GET /v3/instruments/{instrument}/candles`
+#[derive(Debug, Clone)]
+pub struct GetCandles {
+    pub instrument: String,
+    pub query: HashMap<String, String>,
+}
+
+impl Endpoint for GetCandles {
+    type Response = CandlesResponse;
+
+    fn path(&self) -> String {
+        format!("/v3/instruments/{}/candles", self.instrument)
+    }
+
+    fn query(&self) -> Option<HashMap<String, String>> {
+        Some(self.query.clone())
     }
 }
 
 
 impl OandaClient
 {
+    /// `From` and `To` are sent in the client's `Accept-Datetime-Format` unless the query sets
+    /// its own `datetime_format`.
     pub async fn get_candles(
         &mut self,
         instrument: &str,
-        query: HashMap<String, String>,
+        query: impl Into<CandleQuery>,
     ) -> Result<CandlesResponse, APIError> {
-        let mut url = format!("/v3/instruments/{}/candles?", instrument);
-
-        for (key, value) in query {
-            url.push_str(&format!("{}={}&", key, value));
-        }
+        let query = query.into().build_for(self.get_datetime_format());
+        self.execute(&GetCandles { instrument: instrument.to_string(), query }).await
+    }
 
-        let response = self.get(&url).await?;
-        let candles: CandlesResponse = serde_json::from_value(response)?;
-        Ok(candles)
+    pub async fn get_candles_with_response(
+        &mut self,
+        instrument: &str,
+        query: impl Into<CandleQuery>,
+    ) -> Result<Response<CandlesResponse>, APIError> {
+        let query = query.into().build_for(self.get_datetime_format());
+        self.execute_with_response(&GetCandles { instrument: instrument.to_string(), query }).await
     }
 }
 
-
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
 
     use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Instant};
@@ -204,31 +276,13 @@ mod tests {
 
     #[allow(unused_imports)]
     use super::*;
+    use crate::mock::{MockServer, MOCK_ACCOUNT_ID, MOCK_API_KEY};
+    use crate::primitives::datetime;
 
     #[tokio::test]
     async fn test_get_candles() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY")
-            .expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID")
-            .expect("OANDA_ACCOUNT_ID must be set");
-        let client_result = OandaClient::new(
-                    Some(&account_id), 
-                    &api_key, 
-                    100,
-                    100,
-                    100,
-                    5
-                );
-
-        let mut client = match client_result {
-            Ok(v) => v,
-            Err(e) => {
-                println!("Error: {}", e);
-                assert!(false);
-                return;
-            }
-        };
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
 
         let mut query = CandleQuery::new();
         query.add_param("count", CandleQueryParam::Count(5));
@@ -249,27 +303,33 @@ mod tests {
     }
 
 
+    #[cfg(feature = "chrono")]
+    #[tokio::test]
+    async fn test_candle_times_follow_client_format() {
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client_builder().datetime_format(DatetimeFormat::UNIX).build().unwrap();
+
+        let mut query = CandleQuery::new();
+        query.add_param("from", CandleQueryParam::From(datetime::parse("2021-01-04T00:00:00Z").unwrap()));
+        client.get_candles("EUR_USD", query.clone()).await.unwrap();
+
+        // A format set on the query wins over the client's.
+        query.datetime_format(DatetimeFormat::RFC3339);
+        client.get_candles("EUR_USD", query).await.unwrap();
+
+        let requests = server.received_requests();
+        assert_eq!(requests[0].query_param("from"), Some("1609718400.000000000"));
+        assert_eq!(requests[1].query_param("from"), Some("2021-01-04T00:00:00.000000000Z"));
+    }
+
     #[tokio::test]
     async fn test_get_candles_from_to() {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY")
-            .expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID")
-            .expect("OANDA_ACCOUNT_ID must be set");
-
-        let client_result = OandaClient::new(Some(&account_id), &api_key, 100, 100, 100, 5);
-        let mut client = match client_result {
-            Ok(v) => v,
-            Err(e) => {
-                println!("Error: {}", e);
-                assert!(false);
-                return;
-            }
-        };
+        let server = MockServer::start().await.with_fixtures();
+        let mut client = server.client();
 
         let mut query = CandleQuery::new();
-        query.add_param("from", CandleQueryParam::From("2021-01-04T00:00:00Z".to_string()));
-        query.add_param("to", CandleQueryParam::To("2021-01-05T00:00:00Z".to_string()));
+        query.add_param("from", CandleQueryParam::From(datetime::parse("2021-01-04T00:00:00Z").unwrap()));
+        query.add_param("to", CandleQueryParam::To(datetime::parse("2021-01-05T00:00:00Z").unwrap()));
         query.add_param("granularity", CandleQueryParam::Granularity(Granularity::H1));
 
         let response = client.get_candles("EUR_USD", query.build()).await;
@@ -289,20 +349,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_candles_async() -> Result<(), APIError> {
-        dotenv::dotenv().ok();
-        let api_key = std::env::var("OANDA_API_KEY")
-            .expect("OANDA_API_KEY must be set");
-        let account_id = std::env::var("OANDA_ACCOUNT_ID")
-            .expect("OANDA_ACCOUNT_ID must be set");
-
-        let client = OandaClient::new(
-            Some(&account_id), 
-            &api_key, 
-            100,
-            100,
-            100,
-            5
-        ).unwrap();
+        let server = MockServer::start().await.with_fixtures();
+        let client = server.client();
 
         let dates = vec![
                 ["2023-12-30T12:00:00Z", "2024-01-02T23:20:00Z"], 
@@ -386,8 +434,8 @@ mod tests {
                 let mut client = client.clone();
                 async move {
                 let mut query = CandleQuery::new();
-                query.add_param("from", CandleQueryParam::From(date[0].to_string()));
-                query.add_param("to", CandleQueryParam::To(date[1].to_string()));
+                query.add_param("from", CandleQueryParam::From(datetime::parse(date[0]).unwrap()));
+                query.add_param("to", CandleQueryParam::To(datetime::parse(date[1]).unwrap()));
                 query.add_param("granularity", CandleQueryParam::Granularity(Granularity::M1));
                 let json = client
                     .get_candles("EUR_USD", query.build())
@@ -424,6 +472,7 @@ mod tests {
         // End timing
         let duration = start.elapsed();
         println!("Time elapsed in expensive_function() is: {:?}", duration);
+        assert_eq!(count.load(Ordering::SeqCst), server.hits(reqwest::Method::GET, "/v3/instruments/EUR_USD/candles"));
         Ok(())
     }
 
@@ -431,6 +480,7 @@ mod tests {
 // of oanda_rs and raw reqwest client
 #[tokio::test]
 async fn test_get_candles_from_oanda() -> Result<(), APIError> {
+    let server = MockServer::start().await.with_fixtures();
     let client = reqwest::Client::new();
 
     let dates = vec![
@@ -511,7 +561,8 @@ async fn test_get_candles_from_oanda() -> Result<(), APIError> {
 
     for date_batch in dates {
         let url = format!(
-            "https://api-fxpractice.oanda.com/v3/instruments/EUR_USD/candles?from={}&to={}&granularity=M1",
+            "{}/v3/instruments/EUR_USD/candles?from={}&to={}&granularity=M1",
+            server.url(),
             date_batch.first().unwrap(),
             date_batch.last().unwrap()
         );
@@ -519,13 +570,9 @@ async fn test_get_candles_from_oanda() -> Result<(), APIError> {
     }
 
     // Create headers
-    dotenv::dotenv().ok();
-    let api_key = std::env::var("OANDA_API_KEY").expect("OANDA_API_KEY must be set");
-    let account_id = std::env::var("OANDA_ACCOUNT_ID").expect("OANDA_ACCOUNT_ID must be set");
-
     let mut headers = HeaderMap::new();
-    headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap());
-    headers.insert("Account-ID", HeaderValue::from_str(&account_id).unwrap());
+    headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", MOCK_API_KEY)).unwrap());
+    headers.insert("Account-ID", HeaderValue::from_str(MOCK_ACCOUNT_ID).unwrap());
 
     // Start timing
     let start = Instant::now();
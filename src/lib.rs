@@ -1,6 +1,17 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cassette;
 pub mod client;
+pub mod config;
+pub mod credentials;
+pub mod endpoint;
+pub mod environment;
 pub mod error;
+pub mod response;
 pub mod account;
 pub mod instrument;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod utils;
-pub mod policies;
\ No newline at end of file
+pub mod policies;
+pub mod primitives;
\ No newline at end of file
@@ -0,0 +1,206 @@
+//! Canned v20 responses for the endpoints the crate wraps.
+
+use reqwest::Method;
+use serde_json::{json, Value};
+
+use super::{MockResponse, MockServer, ReceivedRequest, MOCK_ACCOUNT_ID};
+
+
+/// Last transaction ID reported by every fixture.
+pub const LAST_TRANSACTION_ID: &str = "6357";
+
+
+pub fn accounts() -> Value {
+    json!({
+        "accounts": [
+            { "id": MOCK_ACCOUNT_ID, "tags": [] }
+        ]
+    })
+}
+
+fn account_fields() -> Value {
+    json!({
+        "NAV": "100000.0000",
+        "alias": "Primary",
+        "balance": "100000.0000",
+        "commission": "0.0000",
+        "createdByUserID": 1234567,
+        "createdTime": "2024-01-02T10:00:00.000000000Z",
+        "currency": "USD",
+        "dividendAdjustment": "0",
+        "financing": "-1.2345",
+        "guaranteedExecutionFees": "0.0000",
+        "guaranteedStopLossOrderMode": "DISABLED",
+        "hedgingEnabled": false,
+        "id": MOCK_ACCOUNT_ID,
+        "lastTransactionID": LAST_TRANSACTION_ID,
+        "marginAvailable": "100000.0000",
+        "marginCallMarginUsed": "0.0000",
+        "marginCallPercent": "0.00000",
+        "marginCloseoutMarginUsed": "0.0000",
+        "marginCloseoutNAV": "100000.0000",
+        "marginCloseoutPercent": "0.00000",
+        "marginCloseoutPositionValue": "0.0000",
+        "marginCloseoutUnrealizedPL": "0.0000",
+        "marginRate": "0.02",
+        "marginUsed": "0.0000",
+        "openPositionCount": 0,
+        "openTradeCount": 0,
+        "pendingOrderCount": 0,
+        "pl": "0.0000",
+        "positionValue": "0.0000",
+        "resettablePL": "0.0000",
+        "resettablePLTime": "0",
+        "unrealizedPL": "0.0000",
+        "withdrawalLimit": "100000.0000"
+    })
+}
+
+pub fn account_summary() -> Value {
+    json!({
+        "account": account_fields(),
+        "lastTransactionID": LAST_TRANSACTION_ID
+    })
+}
+
+pub fn account() -> Value {
+    let mut account = account_fields();
+    account["orders"] = json!([]);
+    account["positions"] = json!([]);
+    account["trades"] = json!([]);
+    json!({
+        "account": account,
+        "lastTransactionID": LAST_TRANSACTION_ID
+    })
+}
+
+pub fn account_instruments() -> Value {
+    json!({
+        "instruments": [
+            {
+                "displayName": "EUR/USD",
+                "displayPrecision": 5,
+                "financing": {
+                    "financingDaysOfWeek": [
+                        { "dayOfWeek": "MONDAY", "daysCharged": 1 },
+                        { "dayOfWeek": "WEDNESDAY", "daysCharged": 3 }
+                    ],
+                    "longRate": "-0.0567",
+                    "shortRate": "0.0318"
+                },
+                "guaranteedStopLossOrderMode": "DISABLED",
+                "marginRate": "0.0333",
+                "maximumOrderUnits": "100000000",
+                "maximumPositionSize": "0",
+                "maximumTrailingStopDistance": "1.00000",
+                "minimumTradeSize": "1",
+                "minimumTrailingStopDistance": "0.00050",
+                "name": "EUR_USD",
+                "pipLocation": -4,
+                "tags": [{ "name": "MAJOR", "type": "ASSET_CLASS" }],
+                "tradeUnitsPrecision": 0,
+                "type": "CURRENCY"
+            }
+        ],
+        "lastTransactionID": LAST_TRANSACTION_ID
+    })
+}
+
+pub fn account_changes() -> Value {
+    json!({
+        "changes": {
+            "ordersCancelled": [],
+            "ordersCreated": [],
+            "ordersFilled": [],
+            "ordersTriggered": [],
+            "positions": [],
+            "tradesClosed": [],
+            "tradesOpened": [],
+            "tradesReduced": [],
+            "transactions": []
+        },
+        "lastTransactionID": LAST_TRANSACTION_ID,
+        "state": {
+            "NAV": "100000.0000",
+            "marginAvailable": "100000.0000",
+            "marginCloseoutMarginUsed": "0.0000",
+            "marginCloseoutNAV": "100000.0000",
+            "marginCloseoutPercent": "0.00000",
+            "marginCloseoutUnrealizedPL": "0.0000",
+            "marginUsed": "0.0000",
+            "orders": [],
+            "positionValue": "0.0000",
+            "positions": [],
+            "trades": [],
+            "unrealizedPL": "0.0000",
+            "withdrawalLimit": "100000.0000"
+        }
+    })
+}
+
+pub fn configuration(alias: Option<&str>, margin_rate: Option<&str>) -> Value {
+    json!({
+        "clientConfigureTransaction": {
+            "accountID": MOCK_ACCOUNT_ID,
+            "alias": alias,
+            "batchID": "6358",
+            "id": "6358",
+            "marginRate": margin_rate,
+            "time": "2024-08-31T17:58:17.000000000Z",
+            "type": "CLIENT_CONFIGURE",
+            "userID": 1234567
+        },
+        "lastTransactionID": "6358"
+    })
+}
+
+/// `count` hourly EUR/USD-like candles starting at 2024-01-02T00:00:00Z.
+pub fn candles(instrument: &str, granularity: &str, count: usize) -> Value {
+    let candles: Vec<Value> = (0..count)
+        .map(|i| {
+            json!({
+                "complete": true,
+                "mid": {
+                    "o": format!("1.{:05}", 10000 + i * 10),
+                    "h": format!("1.{:05}", 10020 + i * 10),
+                    "l": format!("1.{:05}", 9990 + i * 10),
+                    "c": format!("1.{:05}", 10010 + i * 10)
+                },
+                "time": format!("2024-01-02T{:02}:00:00.000000000Z", i % 24),
+                "volume": 100 + i
+            })
+        })
+        .collect();
+    json!({
+        "candles": candles,
+        "granularity": granularity,
+        "instrument": instrument
+    })
+}
+
+fn candles_for(request: &ReceivedRequest) -> MockResponse {
+    let instrument = request.path.split('/').nth(3).unwrap_or("EUR_USD");
+    let granularity = request.query_param("granularity").unwrap_or("S5");
+    let count = request
+        .query_param("count")
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(10);
+    MockResponse::ok(candles(instrument, granularity, count))
+}
+
+fn configuration_for(request: &ReceivedRequest) -> MockResponse {
+    let body = request.json().unwrap_or_default();
+    MockResponse::ok(configuration(body["alias"].as_str(), body["marginRate"].as_str()))
+}
+
+
+/// Mount a fixture for every endpoint the crate wraps.
+pub fn mount_all(server: &MockServer) {
+    server.mock(Method::GET, "/v3/accounts").respond_with(MockResponse::ok(accounts()));
+    server.mock(Method::GET, "/v3/accounts/*").respond_with(MockResponse::ok(account()));
+    server.mock(Method::GET, "/v3/accounts/*/summary").respond_with(MockResponse::ok(account_summary()));
+    server.mock(Method::GET, "/v3/accounts/*/instruments").respond_with(MockResponse::ok(account_instruments()));
+    server.mock(Method::GET, "/v3/accounts/*/changes").respond_with(MockResponse::ok(account_changes()));
+    server.mock(Method::PATCH, "/v3/accounts/*/configuration").respond_with_fn(configuration_for);
+    server.mock(Method::GET, "/v3/instruments/*/candles").respond_with_fn(candles_for);
+}
@@ -0,0 +1,558 @@
+//! In-process stand-in for the OANDA v20 REST and streaming endpoints.
+//!
+//! Available in the crate's own tests and, for downstream crates, behind the `mock` feature.
+//! The server speaks just enough HTTP/1.1 for `reqwest`, so tests run offline and
+//! deterministically:
+//!
+//! ```no_run
+//! use oanda_rs::mock::{MockServer, MockResponse};
+//! use reqwest::Method;
+//!
+//! # async fn run() -> Result<(), oanda_rs::error::APIError> {
+//! let server = MockServer::start().await.with_fixtures();
+//! server
+//!     .mock(Method::GET, "/v3/accounts/*/summary")
+//!     .fail_times(2, MockResponse::rate_limited(1))
+//!     .mount();
+//!
+//! let mut client = server.client();
+//! let summary = client.get_account_summary().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod fixtures;
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::{Method, StatusCode, Url};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::client::OandaClient;
+use crate::config::OandaClientBuilder;
+use crate::environment::Environment;
+
+
+/// Account ID used by `MockServer::client` and the fixtures.
+pub const MOCK_ACCOUNT_ID: &str = "101-001-1234567-001";
+/// Token used by `MockServer::client`.
+pub const MOCK_API_KEY: &str = "mock-api-key";
+
+
+/// A request the mock server received.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_slice(&self.body).ok()
+    }
+}
+
+
+#[derive(Debug, Clone)]
+enum MockBody {
+    Bytes(Vec<u8>),
+    /// Newline-delimited JSON sent with chunked encoding, like the v20 streaming endpoints.
+    Stream(Vec<Value>),
+}
+
+/// A scripted response.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: MockBody,
+    delay: Option<Duration>,
+    body_delay: Option<Duration>,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: Value) -> Self {
+        MockResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: MockBody::Bytes(body.to_string().into_bytes()),
+            delay: None,
+            body_delay: None,
+        }
+    }
+
+    pub fn ok(body: Value) -> Self {
+        MockResponse::json(200, body)
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        MockResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: MockBody::Bytes(body.as_bytes().to_vec()),
+            delay: None,
+            body_delay: None,
+        }
+    }
+
+    /// An OANDA error body with the given status.
+    pub fn error(status: u16, error_code: Option<&str>, error_message: &str) -> Self {
+        let mut body = json!({ "errorMessage": error_message });
+        if let Some(code) = error_code {
+            body["errorCode"] = json!(code);
+        }
+        MockResponse::json(status, body)
+    }
+
+    /// A 429 with a `Retry-After` header.
+    pub fn rate_limited(retry_after_secs: u64) -> Self {
+        MockResponse::error(429, None, "Rate limit violation. Please try again later.")
+            .header("Retry-After", &retry_after_secs.to_string())
+    }
+
+    /// A streaming response: each value is sent as one line, then the stream ends.
+    pub fn stream(lines: Vec<Value>) -> Self {
+        MockResponse {
+            status: 200,
+            headers: vec![("Content-Type".to_string(), "application/octet-stream".to_string())],
+            body: MockBody::Stream(lines),
+            delay: None,
+            body_delay: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Wait before answering, to simulate a slow server.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Send the headers at once but wait before sending the body, to simulate a stalled read.
+    pub fn delay_body(mut self, delay: Duration) -> Self {
+        self.body_delay = Some(delay);
+        self
+    }
+}
+
+
+type Responder = Arc<dyn Fn(&ReceivedRequest) -> MockResponse + Send + Sync>;
+
+struct Mock {
+    method: Method,
+    path: String,
+    scripted: VecDeque<MockResponse>,
+    responder: Responder,
+}
+
+impl Mock {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        if self.method != method {
+            return false;
+        }
+        let expected: Vec<&str> = self.path.trim_matches('/').split('/').collect();
+        let actual: Vec<&str> = path.trim_matches('/').split('/').collect();
+        expected.len() == actual.len()
+            && expected.iter().zip(actual.iter()).all(|(e, a)| *e == "*" || e == a)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    mocks: Vec<Mock>,
+    requests: Vec<ReceivedRequest>,
+    rate_limit: Option<(usize, VecDeque<Instant>)>,
+}
+
+impl State {
+    /// Like OANDA, every response carries a `RequestID`, unless the mock sets its own.
+    fn respond(&mut self, request: &ReceivedRequest) -> MockResponse {
+        self.requests.push(request.clone());
+        let request_id = format!("{}", 24829548730538000u64 + self.requests.len() as u64);
+        let response = self.route(request);
+        if response.headers.iter().any(|(key, _)| key.eq_ignore_ascii_case("RequestID")) {
+            response
+        } else {
+            response.header("RequestID", &request_id)
+        }
+    }
+
+    fn route(&mut self, request: &ReceivedRequest) -> MockResponse {
+
+        if let Some((limit, window)) = &mut self.rate_limit {
+            let now = Instant::now();
+            while window.front().is_some_and(|t| now.duration_since(*t) >= Duration::from_secs(1)) {
+                window.pop_front();
+            }
+            if window.len() >= *limit {
+                return MockResponse::rate_limited(1);
+            }
+            window.push_back(now);
+        }
+
+        // Later mocks take precedence, so a test can override a fixture.
+        match self.mocks.iter_mut().rev().find(|m| m.matches(&request.method, &request.path)) {
+            Some(mock) => match mock.scripted.pop_front() {
+                Some(response) => response,
+                None => (mock.responder)(request),
+            },
+            None => MockResponse::error(
+                404,
+                None,
+                &format!("No mock for {} {}", request.method, request.path),
+            ),
+        }
+    }
+}
+
+
+/// Registers a mock; nothing is served until `mount` or `respond_with` is called.
+pub struct MockBuilder<'a> {
+    server: &'a MockServer,
+    method: Method,
+    path: String,
+    scripted: VecDeque<MockResponse>,
+}
+
+impl<'a> MockBuilder<'a> {
+    /// Answer the next `times` matching requests with `response` before the regular answer.
+    pub fn fail_times(mut self, times: usize, response: MockResponse) -> Self {
+        for _ in 0..times {
+            self.scripted.push_back(response.clone());
+        }
+        self
+    }
+
+    /// Answer the next matching request with `response` before the regular answer.
+    pub fn then(mut self, response: MockResponse) -> Self {
+        self.scripted.push_back(response);
+        self
+    }
+
+    pub fn respond_with(self, response: MockResponse) {
+        self.respond_with_fn(move |_| response.clone())
+    }
+
+    /// Compute the answer from the request, e.g. to echo query parameters.
+    pub fn respond_with_fn<F>(self, responder: F)
+    where
+        F: Fn(&ReceivedRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        self.server.state.lock().unwrap().mocks.push(Mock {
+            method: self.method,
+            path: self.path,
+            scripted: self.scripted,
+            responder: Arc::new(responder),
+        });
+    }
+
+    /// Keep the answer of an earlier mock for the same route after the scripted responses.
+    pub fn mount(self) {
+        let fallback = {
+            let state = self.server.state.lock().unwrap();
+            state
+                .mocks
+                .iter()
+                .rev()
+                .find(|m| m.method == self.method && m.path == self.path)
+                .map(|m| m.responder.clone())
+        };
+        match fallback {
+            Some(responder) => self.respond_with_fn(move |request| responder(request)),
+            None => {
+                let message = format!("No response configured for {} {}", self.method, self.path);
+                self.respond_with(MockResponse::error(404, None, &message))
+            }
+        }
+    }
+}
+
+
+/// A local HTTP server standing in for OANDA. It stops when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Bind to a free port on localhost and start serving.
+    pub async fn start() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind the mock server");
+        let addr = listener.local_addr().expect("mock server has no local address");
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let accept_state = state.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = accept_state.clone();
+                tokio::spawn(async move {
+                    let _ = serve_connection(stream, state).await;
+                });
+            }
+        });
+
+        MockServer { addr, state, handle }
+    }
+
+    /// Mount the default fixtures for every endpoint the crate wraps.
+    pub fn with_fixtures(self) -> Self {
+        fixtures::mount_all(&self);
+        self
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// A custom environment pointing both the REST and the streaming host at this server.
+    pub fn environment(&self) -> Environment {
+        Environment::Custom {
+            rest_url: self.url(),
+            stream_url: self.url(),
+        }
+    }
+
+    /// A builder preset with this server's environment, `MOCK_API_KEY` and `MOCK_ACCOUNT_ID`.
+    pub fn client_builder(&self) -> OandaClientBuilder {
+        OandaClientBuilder::new()
+            .environment(self.environment())
+            .api_key(MOCK_API_KEY)
+            .account_id(MOCK_ACCOUNT_ID)
+            .rate_limit(1000)
+    }
+
+    pub fn client(&self) -> OandaClient {
+        self.client_builder()
+            .build()
+            .expect("failed to build a client for the mock server")
+    }
+
+    /// Start describing the answer for `method` on `path`. A `*` segment matches any value.
+    pub fn mock(&self, method: Method, path: &str) -> MockBuilder<'_> {
+        MockBuilder {
+            server: self,
+            method,
+            path: path.to_string(),
+            scripted: VecDeque::new(),
+        }
+    }
+
+    /// Answer with 429 once more than `per_second` requests arrive within a second.
+    pub fn rate_limit(&self, per_second: usize) {
+        self.state.lock().unwrap().rate_limit = Some((per_second, VecDeque::new()));
+    }
+
+    pub fn received_requests(&self) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Number of requests received for `method` on `path` (with `*` wildcards).
+    pub fn hits(&self, method: Method, path: &str) -> usize {
+        let probe = Mock {
+            method,
+            path: path.to_string(),
+            scripted: VecDeque::new(),
+            responder: Arc::new(|_| MockResponse::ok(Value::Null)),
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|r| probe.matches(&r.method, &r.path))
+            .count()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+
+async fn serve_connection(stream: TcpStream, state: Arc<Mutex<State>>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    while let Some(request) = read_request(&mut reader).await? {
+        let response = state.lock().unwrap().respond(&request);
+        if let Some(delay) = response.delay {
+            tokio::time::sleep(delay).await;
+        }
+        let keep_alive = write_response(&mut writer, response).await?;
+        if !keep_alive {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn read_request<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> std::io::Result<Option<ReceivedRequest>> {
+    let mut head = Vec::new();
+    loop {
+        let byte = match reader.read_u8().await {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        head.push(byte);
+        if head.ends_with(b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid request line");
+    let method: Method = request_line.next().ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+    let target = request_line.next().ok_or_else(invalid)?;
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    let url = Url::parse(&format!("http://localhost{}", target)).map_err(|_| invalid())?;
+    Ok(Some(ReceivedRequest {
+        method,
+        path: url.path().to_string(),
+        query: url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect(),
+        headers,
+        body,
+    }))
+}
+
+/// Returns whether the connection can be reused.
+async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, response: MockResponse) -> std::io::Result<bool> {
+    let reason = StatusCode::from_u16(response.status)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or("Unknown");
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason);
+    for (key, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", key, value));
+    }
+
+    match response.body {
+        MockBody::Bytes(body) => {
+            head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+            writer.write_all(head.as_bytes()).await?;
+            if let Some(delay) = response.body_delay {
+                writer.flush().await?;
+                tokio::time::sleep(delay).await;
+            }
+            writer.write_all(&body).await?;
+            writer.flush().await?;
+            Ok(true)
+        }
+        MockBody::Stream(lines) => {
+            head.push_str("Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n");
+            writer.write_all(head.as_bytes()).await?;
+            for line in lines {
+                let chunk = format!("{}\n", line);
+                writer.write_all(format!("{:x}\r\n{}\r\n", chunk.len(), chunk).as_bytes()).await?;
+                writer.flush().await?;
+            }
+            writer.write_all(b"0\r\n\r\n").await?;
+            writer.flush().await?;
+            Ok(false)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scripted_failures_then_fixture() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts")
+            .fail_times(1, MockResponse::error(503, None, "Service unavailable"))
+            .mount();
+
+        let http = reqwest::Client::new();
+        let url = format!("{}/v3/accounts", server.url());
+        assert_eq!(http.get(&url).send().await.unwrap().status(), 503);
+        assert_eq!(http.get(&url).send().await.unwrap().status(), 200);
+        assert_eq!(server.hits(Method::GET, "/v3/accounts"), 2);
+
+        let missing = http.get(format!("{}/v3/unknown", server.url())).send().await.unwrap();
+        assert_eq!(missing.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_simulation() {
+        let server = MockServer::start().await.with_fixtures();
+        server.rate_limit(2);
+
+        let http = reqwest::Client::new();
+        let url = format!("{}/v3/accounts", server.url());
+        let mut statuses = Vec::new();
+        for _ in 0..3 {
+            statuses.push(http.get(&url).send().await.unwrap());
+        }
+        assert_eq!(statuses[1].status(), 200);
+        assert_eq!(statuses[2].status(), 429);
+        assert_eq!(statuses[2].headers()["Retry-After"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let server = MockServer::start().await;
+        server
+            .mock(Method::GET, "/v3/accounts/*/pricing/stream")
+            .respond_with(MockResponse::stream(vec![
+                json!({"type": "HEARTBEAT"}),
+                json!({"type": "PRICE", "instrument": "EUR_USD"}),
+            ]));
+
+        let url = format!("{}/v3/accounts/{}/pricing/stream", server.url(), MOCK_ACCOUNT_ID);
+        let body = reqwest::get(&url).await.unwrap().text().await.unwrap();
+        let lines: Vec<Value> = body.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["instrument"], "EUR_USD");
+    }
+}
@@ -0,0 +1,382 @@
+//! A request rate shared by a client and its clones, which slows down when OANDA answers 429.
+//!
+//! `AdaptiveRate` is a token bucket holding up to one second of requests. On a 429 it stops
+//! handing out slots until `Retry-After` has passed (or, without that header, for a backoff that
+//! doubles with each 429 in a row) and halves its rate; requests already waiting for a slot are
+//! held until the pause ends as well. The rate then climbs back by a tenth of the configured
+//! rate per second without another 429, so bulk jobs slow themselves down instead of failing.
+
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use tokio::time::{sleep_until, Duration, Instant};
+use tower::{Layer, Service};
+
+use crate::utils::clonable_request::ClonableRequest;
+
+
+/// The lowest rate a run of 429s can push the limiter down to, in requests per second.
+pub const MIN_RATE: f64 = 1.0;
+/// The pause after a 429 without `Retry-After`, doubled for each further 429 in a row.
+pub const BASE_PAUSE: Duration = Duration::from_secs(1);
+pub const MAX_PAUSE: Duration = Duration::from_secs(30);
+/// Share of the configured rate regained for every second without a 429.
+const RECOVERY_PER_SECOND: f64 = 0.1;
+
+
+/// The `Retry-After` delay of a response, when it is given in seconds as OANDA does.
+pub fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+
+#[derive(Debug)]
+struct State {
+    max_rate: f64,
+    /// The rate right after the last 429, and when that was.
+    reduced_rate: f64,
+    reduced_at: Instant,
+    tokens: f64,
+    refilled_at: Instant,
+    paused_until: Instant,
+    consecutive_429: u32,
+}
+
+impl State {
+    fn rate(&self, now: Instant) -> f64 {
+        let recovered = now.saturating_duration_since(self.reduced_at).as_secs_f64() * RECOVERY_PER_SECOND * self.max_rate;
+        (self.reduced_rate + recovered).min(self.max_rate)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.refilled_at {
+            let rate = self.rate(now);
+            let elapsed = (now - self.refilled_at).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(rate);
+            self.refilled_at = now;
+        }
+    }
+}
+
+
+/// The shared rate. Clones refer to the same bucket.
+#[derive(Clone, Debug)]
+pub struct AdaptiveRate {
+    state: Arc<Mutex<State>>,
+}
+
+impl AdaptiveRate {
+    pub fn new(rate_per_second: usize) -> AdaptiveRate {
+        let rate = (rate_per_second as f64).max(MIN_RATE);
+        let now = Instant::now();
+        AdaptiveRate {
+            state: Arc::new(Mutex::new(State {
+                max_rate: rate,
+                reduced_rate: rate,
+                reduced_at: now,
+                tokens: rate,
+                refilled_at: now,
+                paused_until: now,
+                consecutive_429: 0,
+            })),
+        }
+    }
+
+    /// The rate in use right now, in requests per second.
+    pub fn current_rate(&self) -> f64 {
+        self.state.lock().unwrap().rate(Instant::now())
+    }
+
+    /// Take the next slot and return when it starts. Slots are handed out in order.
+    pub fn reserve(&self) -> Instant {
+        let mut state = self.state.lock().unwrap();
+        let start = Instant::now().max(state.paused_until);
+        state.refill(start);
+        state.tokens -= 1.0;
+        if state.tokens >= 0.0 {
+            start
+        } else {
+            start + Duration::from_secs_f64(-state.tokens / state.rate(start))
+        }
+    }
+
+    /// Sleep until a slot from `reserve` starts. If a 429 paused the rate in the meantime, the
+    /// slot no longer counts: take a new one after the pause and wait for that instead.
+    pub async fn wait(&self, mut start: Instant) {
+        loop {
+            sleep_until(start).await;
+            let paused = Instant::now() < self.state.lock().unwrap().paused_until;
+            if !paused {
+                return;
+            }
+            start = self.reserve();
+        }
+    }
+
+    /// OANDA rejected a request for going too fast.
+    pub fn rate_limited(&self, retry_after: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let pause = retry_after.unwrap_or_else(|| {
+            BASE_PAUSE
+                .saturating_mul(2u32.saturating_pow(state.consecutive_429))
+                .min(MAX_PAUSE)
+        });
+        state.consecutive_429 = state.consecutive_429.saturating_add(1);
+
+        let rate = state.rate(now);
+        state.reduced_rate = (rate / 2.0).max(MIN_RATE);
+        state.reduced_at = now + pause;
+        state.paused_until = state.paused_until.max(now + pause);
+        state.refilled_at = state.paused_until;
+        // Slots already handed out are taken again after the pause when they come up (see `wait`);
+        // the first one after the pause goes straight away.
+        state.tokens = 1.0;
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            pause_ms = pause.as_millis() as u64,
+            rate = state.reduced_rate,
+            "rate limited by OANDA, slowing down"
+        );
+    }
+
+    /// OANDA accepted a request, so the next 429 starts the backoff over.
+    pub fn accepted(&self) {
+        self.state.lock().unwrap().consecutive_429 = 0;
+    }
+
+    /// Waits for a slot before each request.
+    pub fn limit_layer(&self) -> AdaptiveRateLayer {
+        AdaptiveRateLayer { rate: self.clone(), role: Role::Limit }
+    }
+
+    /// Watches every attempt, retries included, for 429s.
+    pub fn feedback_layer(&self) -> AdaptiveRateLayer {
+        AdaptiveRateLayer { rate: self.clone(), role: Role::Feedback }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Limit,
+    Feedback,
+}
+
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveRateLayer {
+    rate: AdaptiveRate,
+    role: Role,
+}
+
+impl<S> Layer<S> for AdaptiveRateLayer {
+    type Service = AdaptiveRateService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AdaptiveRateService {
+            inner,
+            rate: self.rate.clone(),
+            role: self.role,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveRateService<S> {
+    inner: S,
+    rate: AdaptiveRate,
+    role: Role,
+}
+
+impl<S> Service<ClonableRequest> for AdaptiveRateService<S>
+where
+    S: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ClonableRequest) -> Self::Future {
+        match self.role {
+            Role::Limit => {
+                let rate = self.rate.clone();
+                let start = rate.reserve();
+                // The ready service goes into the future; this one keeps a fresh clone.
+                let clone = self.inner.clone();
+                let mut inner = std::mem::replace(&mut self.inner, clone);
+                Box::pin(async move {
+                    rate.wait(start).await;
+                    inner.call(req).await
+                })
+            }
+            Role::Feedback => {
+                let rate = self.rate.clone();
+                let future = self.inner.call(req);
+                Box::pin(async move {
+                    let result = future.await;
+                    if let Ok(response) = &result {
+                        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                            rate.rate_limited(retry_after(response));
+                        } else {
+                            rate.accepted();
+                        }
+                    }
+                    result
+                })
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backs_off_and_recovers() {
+        let rate = AdaptiveRate::new(10);
+        let start = Instant::now();
+
+        // A full bucket lets a second's worth through at once, then paces the rest.
+        for _ in 0..10 {
+            assert_eq!(rate.reserve(), start);
+        }
+        assert_eq!(rate.reserve(), start + Duration::from_millis(100));
+
+        rate.rate_limited(Some(Duration::from_secs(2)));
+        assert_eq!(rate.current_rate(), 5.0);
+        assert_eq!(rate.reserve(), start + Duration::from_secs(2));
+        assert_eq!(rate.reserve(), start + Duration::from_millis(2200));
+
+        // Without Retry-After the pause doubles with each 429 in a row.
+        rate.rate_limited(None);
+        rate.rate_limited(None);
+        assert_eq!(rate.reserve(), start + Duration::from_secs(4));
+        assert_eq!(rate.current_rate(), 1.25);
+        rate.accepted();
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        assert_eq!(rate.current_rate(), 10.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_waits_for_pause() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tower::ServiceExt;
+
+        use crate::policies::rate_limiter::RateLimiter;
+        use crate::policies::retry_policy::{Jitter, RetryConfig, RetryPolicy};
+        use crate::error::APIError;
+
+        // The first try gets a 429 without Retry-After, the second goes through.
+        let started = Instant::now();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (recorded, count) = (sent.clone(), calls.clone());
+        let service = tower::service_fn(move |_req: ClonableRequest| {
+            recorded.lock().unwrap().push(started.elapsed());
+            let status = if count.fetch_add(1, Ordering::SeqCst) == 0 { 429 } else { 200 };
+            let response = http::Response::builder().status(status).body("").unwrap();
+            async move { Ok::<_, APIError>(reqwest::Response::from(response)) }
+        });
+
+        // A backoff far shorter than the pause the 429 starts.
+        let retry = RetryConfig {
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            jitter: Jitter::None,
+            ..RetryConfig::default()
+        };
+        let limiter = RateLimiter::with_layers(service, 10, 10, 10, RetryPolicy::with_config(1, retry), &[]).unwrap();
+        let request = reqwest::Request::new(Method::GET, "http://localhost/v3/accounts".parse().unwrap());
+        let response = limiter.service.oneshot(ClonableRequest::new(request)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*sent.lock().unwrap(), [Duration::ZERO, BASE_PAUSE]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_requests_wait_for_pause() {
+        use tower::ServiceExt;
+
+        use crate::policies::rate_limiter::RateLimiter;
+        use crate::policies::retry_policy::{RetryConfig, RetryPolicy};
+        use crate::error::APIError;
+
+        // The first request is answered with a 429 after the next two have taken their slots.
+        let started = Instant::now();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sent.clone();
+        let service = tower::service_fn(move |_req: ClonableRequest| {
+            let first = {
+                let mut sent = recorded.lock().unwrap();
+                sent.push(started.elapsed());
+                sent.len() == 1
+            };
+            async move {
+                let response = if first {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    http::Response::builder().status(429).header(RETRY_AFTER, "3").body("").unwrap()
+                } else {
+                    http::Response::builder().status(200).body("").unwrap()
+                };
+                Ok::<_, APIError>(reqwest::Response::from(response))
+            }
+        });
+
+        let limiter = RateLimiter::with_layers(service, 1, 10, 10, RetryPolicy::with_config(0, RetryConfig::default()), &[]).unwrap();
+        let calls = (0..3).map(|_| {
+            let request = reqwest::Request::new(Method::GET, "http://localhost/v3/accounts".parse().unwrap());
+            limiter.service.clone().oneshot(ClonableRequest::new(request))
+        });
+        let responses = futures::future::join_all(calls).await;
+        assert!(responses.iter().all(|response| response.is_ok()));
+
+        // Queued for 1s and 2s, both wait out the pause that ends at 3.5s.
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent[0], Duration::ZERO);
+        assert!(sent[1..].iter().all(|&at| at >= Duration::from_millis(3500)), "{:?}", sent);
+    }
+
+    #[tokio::test]
+    async fn test_slows_down_on_429() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts")
+            .fail_times(1, MockResponse::rate_limited(2))
+            .mount();
+        let mut client = server.client_builder().rate_limit(100).retry_attempts(1).build().unwrap();
+        let other = client.clone();
+
+        let started = Instant::now();
+        client.get_accounts().await.unwrap();
+        // The retry waited for Retry-After, and the clone slowed down too.
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        assert!(other.current_rate_limit() < 100.0);
+        assert_eq!(server.hits(Method::GET, "/v3/accounts"), 2);
+    }
+}
@@ -0,0 +1,338 @@
+//! Stops sending requests for a while once OANDA keeps failing.
+//!
+//! The breaker starts `Closed`. After `failure_threshold` failed attempts in a row it opens, and
+//! every request fails straight away with `APIError::CircuitOpen` instead of reaching OANDA or
+//! being retried. Once `cool_down` has passed it is `HalfOpen`: up to `half_open_max_calls` trial
+//! requests go through, and the first result decides whether it closes again or reopens.
+//!
+//! Failures are transport errors and 5xx responses; 4xx responses mean OANDA is up.
+//!
+//! ```no_run
+//! # async fn run(client: oanda_rs::client::OandaClient) {
+//! if let Some(breaker) = client.circuit_breaker() {
+//!     let mut events = breaker.subscribe();
+//!     while let Ok(event) = events.recv().await {
+//!         println!("OANDA circuit {:?} -> {:?}", event.from, event.to);
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use tokio::sync::broadcast;
+use tokio::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+use crate::error::APIError;
+use crate::utils::clonable_request::ClonableRequest;
+
+
+pub const DEFAULT_FAILURE_THRESHOLD: usize = 5;
+pub const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(30);
+/// Events kept for a subscriber that has fallen behind.
+const EVENT_CAPACITY: usize = 16;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CircuitState {
+    /// Requests are sent.
+    Closed,
+    /// Requests fail with `APIError::CircuitOpen` without being sent.
+    Open,
+    /// A few trial requests are sent to find out whether OANDA has recovered.
+    HalfOpen,
+}
+
+
+/// Published on every state change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitEvent {
+    pub from: CircuitState,
+    pub to: CircuitState,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Failed attempts in a row that open the circuit.
+    pub failure_threshold: usize,
+    /// How long the circuit stays open before trial requests are let through.
+    pub cool_down: Duration,
+    /// Trial requests allowed in flight while half open.
+    pub half_open_max_calls: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cool_down: DEFAULT_COOL_DOWN,
+            half_open_max_calls: 1,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    consecutive_failures: usize,
+    opened_at: Instant,
+    trials_in_flight: usize,
+}
+
+
+/// The breaker shared by a client and its clones.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    config: Arc<CircuitBreakerConfig>,
+    inner: Arc<Mutex<Inner>>,
+    events: broadcast::Sender<CircuitEvent>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            config: Arc::new(config),
+            inner: Arc::new(Mutex::new(Inner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+                trials_in_flight: 0,
+            })),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Receive every state change from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<CircuitEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn layer(&self) -> CircuitBreakerLayer {
+        CircuitBreakerLayer { breaker: self.clone() }
+    }
+
+    fn transition(&self, inner: &mut Inner, to: CircuitState) {
+        let from = inner.state;
+        inner.state = to;
+        match to {
+            CircuitState::Open => inner.opened_at = Instant::now(),
+            CircuitState::Closed => inner.consecutive_failures = 0,
+            CircuitState::HalfOpen => {}
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(?from, ?to, "circuit breaker changed state");
+        // Nobody may be listening.
+        let _ = self.events.send(CircuitEvent { from, to });
+    }
+
+    /// Let a request through, or say how long the circuit stays open.
+    fn admit(&self) -> Result<Permit, Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == CircuitState::Open {
+            let elapsed = inner.opened_at.elapsed();
+            if elapsed < self.config.cool_down {
+                return Err(self.config.cool_down - elapsed);
+            }
+            self.transition(&mut inner, CircuitState::HalfOpen);
+        }
+        if inner.state == CircuitState::HalfOpen {
+            if inner.trials_in_flight >= self.config.half_open_max_calls {
+                return Err(Duration::ZERO);
+            }
+            inner.trials_in_flight += 1;
+        }
+        Ok(Permit {
+            breaker: self.clone(),
+            trial: inner.state == CircuitState::HalfOpen,
+            settled: false,
+        })
+    }
+
+    fn settle(&self, trial: bool, success: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if trial {
+            inner.trials_in_flight -= 1;
+        }
+        match (inner.state, success) {
+            (CircuitState::Closed, true) => inner.consecutive_failures = 0,
+            (CircuitState::Closed, false) => {
+                inner.consecutive_failures += 1;
+                if inner.consecutive_failures >= self.config.failure_threshold {
+                    self.transition(&mut inner, CircuitState::Open);
+                }
+            }
+            (CircuitState::HalfOpen, true) if trial => self.transition(&mut inner, CircuitState::Closed),
+            (CircuitState::HalfOpen, false) if trial => self.transition(&mut inner, CircuitState::Open),
+            // A request admitted before the state changed has nothing left to say.
+            _ => {}
+        }
+    }
+}
+
+
+/// A request the breaker let through. Dropped unsettled, such as when the caller times out,
+/// it frees its trial slot without counting as a success or a failure.
+struct Permit {
+    breaker: CircuitBreaker,
+    trial: bool,
+    settled: bool,
+}
+
+impl Permit {
+    fn settle(mut self, success: bool) {
+        self.settled = true;
+        self.breaker.settle(self.trial, success);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.settled && self.trial {
+            self.breaker.inner.lock().unwrap().trials_in_flight -= 1;
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerLayer {
+    breaker: CircuitBreaker,
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            inner,
+            breaker: self.breaker.clone(),
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerService<S> {
+    inner: S,
+    breaker: CircuitBreaker,
+}
+
+impl<S> Service<ClonableRequest> for CircuitBreakerService<S>
+where
+    S: Service<ClonableRequest, Response = reqwest::Response>,
+    S::Error: From<APIError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ClonableRequest) -> Self::Future {
+        let permit = match self.breaker.admit() {
+            Ok(permit) => permit,
+            Err(remaining) => return Box::pin(async move { Err(APIError::CircuitOpen(remaining).into()) }),
+        };
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let result = future.await;
+            permit.settle(matches!(&result, Ok(response) if !response.status().is_server_error()));
+            result
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use super::*;
+    use crate::mock::{fixtures, MockResponse, MockServer};
+    use crate::policies::retry_policy::{Jitter, RetryConfig};
+
+    #[tokio::test]
+    async fn test_opens_fails_fast_and_recovers() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/summary")
+            .fail_times(2, MockResponse::error(503, None, "Service unavailable"))
+            .then(MockResponse::ok(fixtures::account_summary()))
+            .mount();
+        let mut client = server
+            .client_builder()
+            .retry_attempts(2)
+            .retry_config(RetryConfig {
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(10),
+                jitter: Jitter::None,
+                ..RetryConfig::default()
+            })
+            .circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 2,
+                cool_down: Duration::from_millis(200),
+                half_open_max_calls: 1,
+            })
+            .build()
+            .unwrap();
+        let breaker = client.circuit_breaker().unwrap().clone();
+        let mut events = breaker.subscribe();
+
+        // The second 503 opens the circuit, and the last retry is refused without being sent.
+        let error = client.get_account_summary().await.unwrap_err();
+        assert!(matches!(error, APIError::CircuitOpen(_)), "{:?}", error);
+        assert!(matches!(client.get_accounts().await, Err(APIError::CircuitOpen(_))));
+        assert_eq!(server.hits(Method::GET, "/v3/accounts/*/summary"), 2);
+        assert_eq!(server.hits(Method::GET, "/v3/accounts"), 0);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        client.get_account_summary().await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        let transitions: Vec<(CircuitState, CircuitState)> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| (event.from, event.to))
+            .collect();
+        assert_eq!(
+            transitions,
+            [
+                (CircuitState::Closed, CircuitState::Open),
+                (CircuitState::Open, CircuitState::HalfOpen),
+                (CircuitState::HalfOpen, CircuitState::Closed),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_half_open_trials() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            cool_down: Duration::from_secs(1),
+            half_open_max_calls: 1,
+        });
+        breaker.admit().unwrap().settle(false);
+        assert_eq!(breaker.admit().err(), Some(Duration::from_secs(1)));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let trial = breaker.admit().unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.admit().is_err());
+
+        // An abandoned trial frees its slot; a failed one opens the circuit again.
+        drop(trial);
+        breaker.admit().unwrap().settle(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+}
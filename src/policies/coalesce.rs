@@ -0,0 +1,444 @@
+//! Shares one in-flight response among identical GET requests.
+//!
+//! With `coalesce_requests` enabled, a GET sent while an identical one is still in flight does
+//! not go out again: it waits for the first one and gets a copy of its response. Requests are
+//! identical when they have the same path (and so the account), the same query parameters in any
+//! order and the same `Accept-Datetime-Format`. Only requests in flight at the same time are
+//! shared; nothing is cached once the response is back.
+//!
+//! The shared request goes through the rest of the stack once, so it takes one rate limit slot
+//! however many callers wait for it. It is sent with the deadline and priority of the call that
+//! started it; the calls that joined it keep their own timeouts while they wait, but do not
+//! extend its deadline or raise its priority. The request is dropped once every caller waiting
+//! for it has given up.
+//!
+//! When it fails, every waiter gets the error. The call that started it gets the original, as it
+//! would without coalescing. The others get a copy: status, timeout and circuit errors keep their
+//! `APIError` variant, while transport and decoding errors, which cannot be copied, arrive as
+//! `APIError::Other` with the same message.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use hyper::body::Bytes;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Method, ResponseBuilderExt, StatusCode, Url, Version};
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+use crate::cassette::split_path;
+use crate::error::APIError;
+use crate::utils::clonable_request::ClonableRequest;
+
+
+/// Rebuild an error for another caller. Status, timeout, circuit and configuration errors keep
+/// their variant; transport and decoding errors cannot be copied and become `APIError::Other`.
+fn duplicate(error: &APIError) -> APIError {
+    match error {
+        APIError::Other(message) => APIError::Other(message.clone()),
+        APIError::Clone(message) => APIError::Clone(message.clone()),
+        APIError::Config(message) => APIError::Config(message.clone()),
+        APIError::Timeout(budget) => APIError::Timeout(*budget),
+        APIError::CircuitOpen(remaining) => APIError::CircuitOpen(*remaining),
+        APIError::BadRequest(response) => APIError::BadRequest(response.clone()),
+        APIError::Unauthorized(response) => APIError::Unauthorized(response.clone()),
+        APIError::Forbidden(response) => APIError::Forbidden(response.clone()),
+        APIError::NotFound(response) => APIError::NotFound(response.clone()),
+        APIError::MethodNotAllowed(response) => APIError::MethodNotAllowed(response.clone()),
+        APIError::RateLimited(response) => APIError::RateLimited(response.clone()),
+        APIError::Server(response) => APIError::Server(response.clone()),
+        APIError::UnexpectedStatus(response) => APIError::UnexpectedStatus(response.clone()),
+        APIError::HTTP(_) | APIError::Serde(_) => APIError::Other(error.to_string()),
+    }
+}
+
+
+/// The error of a coalesced request. The caller whose call sent it takes the original; the
+/// others get a copy.
+#[derive(Clone, Debug)]
+struct SharedError {
+    original: Arc<Mutex<Option<BoxError>>>,
+    copy: Arc<APIError>,
+}
+
+impl SharedError {
+    fn new(error: BoxError) -> SharedError {
+        let copy = match error.downcast_ref::<APIError>() {
+            Some(error) => duplicate(error),
+            None => APIError::Other(error.to_string()),
+        };
+        SharedError {
+            original: Arc::new(Mutex::new(Some(error))),
+            copy: Arc::new(copy),
+        }
+    }
+
+    fn into_error(self, leader: bool) -> BoxError {
+        let original = if leader { self.original.lock().unwrap().take() } else { None };
+        original.unwrap_or_else(|| duplicate(&self.copy).into())
+    }
+}
+
+
+/// A response read to the end, so each waiter can get its own copy.
+#[derive(Clone, Debug)]
+struct Buffered {
+    status: StatusCode,
+    version: Version,
+    url: Url,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Buffered {
+    async fn read(response: reqwest::Response) -> Result<Buffered, reqwest::Error> {
+        let (status, version, url, headers) =
+            (response.status(), response.version(), response.url().clone(), response.headers().clone());
+        let body = response.bytes().await?;
+        Ok(Buffered { status, version, url, headers, body })
+    }
+
+    fn response(&self) -> reqwest::Response {
+        let mut builder = http::Response::builder()
+            .status(self.status)
+            .version(self.version)
+            .url(self.url.clone());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = self.headers.clone();
+        }
+        builder
+            .body(self.body.clone())
+            .expect("status and headers come from a valid response")
+            .into()
+    }
+}
+
+
+type InFlight = Shared<BoxFuture<'static, Result<Buffered, SharedError>>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    path: String,
+    query: Vec<String>,
+    datetime_format: Option<HeaderValue>,
+}
+
+impl Key {
+    /// Only GETs without a body are shared.
+    fn of(req: &ClonableRequest) -> Option<Key> {
+        if req.method() != Method::GET || req.body().is_some() {
+            return None;
+        }
+        let (path, query) = split_path(req.url().as_str());
+        Some(Key {
+            path: path.to_string(),
+            query: query.into_iter().map(String::from).collect(),
+            datetime_format: req.headers().get("Accept-Datetime-Format").cloned(),
+        })
+    }
+}
+
+
+/// The requests in flight, shared by a client and its clones.
+#[derive(Clone, Debug, Default)]
+pub struct Coalescer {
+    in_flight: Arc<Mutex<HashMap<Key, InFlight>>>,
+}
+
+impl Coalescer {
+    pub fn new() -> Coalescer {
+        Coalescer::default()
+    }
+
+    pub fn layer(&self) -> CoalesceLayer {
+        CoalesceLayer { coalescer: self.clone() }
+    }
+
+    /// Distinct requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    /// Join the request in flight for `key`, or start it with `start`.
+    fn join(&self, key: Key, start: impl FnOnce() -> InFlight) -> Waiter {
+        let (shared, leader) = match self.in_flight.lock().unwrap().entry(key.clone()) {
+            Entry::Occupied(entry) => (entry.get().clone(), false),
+            Entry::Vacant(entry) => (entry.insert(start()).clone(), true),
+        };
+        Waiter {
+            coalescer: self.clone(),
+            key,
+            leader,
+            polling: Some(shared.clone()),
+            shared,
+        }
+    }
+}
+
+
+/// One caller waiting for a shared request. The last one to leave, whether the response came
+/// back or the caller gave up, takes the request out of the map.
+struct Waiter {
+    coalescer: Coalescer,
+    key: Key,
+    /// Whether this caller's request is the one being sent.
+    leader: bool,
+    /// Never polled: a `Shared` lets go of the request once it has returned the output, and this
+    /// one is still needed to look it up.
+    shared: InFlight,
+    polling: Option<InFlight>,
+}
+
+impl Future for Waiter {
+    type Output = Result<Buffered, SharedError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let polling = self.polling.as_mut().expect("Waiter polled after completion");
+        Pin::new(polling).poll(cx)
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        drop(self.polling.take());
+        let mut in_flight = self.coalescer.in_flight.lock().unwrap();
+        let Some(current) = in_flight.get(&self.key) else { return };
+        // The map holds one clone of the request and this waiter another.
+        let last = self.shared.strong_count().is_none_or(|count| count <= 2);
+        if current.ptr_eq(&self.shared) && (last || self.shared.peek().is_some()) {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct CoalesceLayer {
+    coalescer: Coalescer,
+}
+
+impl<S> Layer<S> for CoalesceLayer {
+    type Service = CoalesceService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CoalesceService {
+            inner,
+            coalescer: self.coalescer.clone(),
+        }
+    }
+}
+
+
+/// Sends identical GETs once and hands every caller a copy of the response.
+#[derive(Clone, Debug)]
+pub struct CoalesceService<S> {
+    inner: S,
+    coalescer: Coalescer,
+}
+
+impl<S> Service<ClonableRequest> for CoalesceService<S>
+where
+    S: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    /// Always ready: a request that joins another is never sent, so it must not take a slot.
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: ClonableRequest) -> Self::Future {
+        let inner = self.inner.clone();
+        let Some(key) = Key::of(&req) else {
+            return Box::pin(async move { inner.oneshot(req).await.map_err(Into::into) });
+        };
+        let waiter = self.coalescer.join(key, move || {
+            let request: BoxFuture<'static, Result<Buffered, SharedError>> = Box::pin(async move {
+                let response = inner.oneshot(req).await.map_err(|e| SharedError::new(e.into()))?;
+                Buffered::read(response).await.map_err(|e| SharedError::new(e.into()))
+            });
+            request.shared()
+        });
+        Box::pin(async move {
+            let leader = waiter.leader;
+            waiter
+                .await
+                .map(|buffered| buffered.response())
+                .map_err(|error| error.into_error(leader))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::Method;
+
+    use super::*;
+    use crate::environment::Environment;
+    use crate::instrument::candles::{CandleQuery, CandleQueryParam, Granularity};
+    use crate::mock::{fixtures, MockResponse, MockServer};
+
+    async fn share_a_response() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::ok(fixtures::account_summary()).delay(Duration::from_millis(100)));
+        let client = server.client_builder().coalesce_requests(true).build().unwrap();
+
+        let calls = (0..5).map(|_| {
+            let mut client = client.clone();
+            async move { client.get_account_summary().await }
+        });
+        let summaries = futures::future::join_all(calls).await;
+        assert!(summaries.iter().all(|summary| summary.is_ok()));
+        assert_eq!(server.hits(Method::GET, "/v3/accounts/*/summary"), 1);
+
+        assert_eq!(client.coalescer().unwrap().in_flight(), 0);
+        // A different path is another request, and nothing is kept once the response is back.
+        let (mut first, mut second) = (client.clone(), client.clone());
+        let (summary, accounts) = tokio::join!(first.get_account_summary(), second.get_accounts());
+        summary.unwrap();
+        accounts.unwrap();
+        assert_eq!(server.hits(Method::GET, "/v3/accounts/*/summary"), 2);
+        assert_eq!(server.hits(Method::GET, "/v3/accounts"), 1);
+    }
+
+    #[test]
+    fn test_identical_gets_share_a_response() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        #[cfg(feature = "metrics")]
+        {
+            // Requests that joined another were never queued, so the gauge comes back to 0.
+            let recorder = crate::policies::metrics::tests::TestRecorder::default();
+            metrics::with_local_recorder(&recorder, || runtime.block_on(share_a_response()));
+            assert_eq!(recorder.get("oanda_buffer_queued{}"), 0.0);
+            assert_eq!(recorder.get("oanda_in_flight{}"), 0.0);
+        }
+        #[cfg(not(feature = "metrics"))]
+        runtime.block_on(share_a_response());
+    }
+
+    #[tokio::test]
+    async fn test_query_order_does_not_matter() {
+        let key = |url: &str| {
+            let request = reqwest::Request::new(Method::GET, Url::parse(url).unwrap());
+            Key::of(&ClonableRequest::new(request)).unwrap()
+        };
+        let base = "http://127.0.0.1/v3/instruments/EUR_USD/candles";
+        assert_eq!(key(&format!("{}?count=2&granularity=S5", base)), key(&format!("{}?granularity=S5&count=2", base)));
+        assert_ne!(key(&format!("{}?count=2&granularity=S5", base)), key(&format!("{}?count=3&granularity=S5", base)));
+
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/instruments/*/candles")
+            .respond_with(MockResponse::ok(fixtures::candles("EUR_USD", "S5", 2)).delay(Duration::from_millis(100)));
+        let client = server.client_builder().coalesce_requests(true).build().unwrap();
+
+        let mut forward = CandleQuery::new();
+        forward
+            .add_param("granularity", CandleQueryParam::Granularity(Granularity::S5))
+            .add_param("count", CandleQueryParam::Count(2))
+            .add_param("price", CandleQueryParam::Price("M".to_string()))
+            .add_param("smooth", CandleQueryParam::Smooth(false))
+            .add_param("dailyAlignment", CandleQueryParam::DailyAlignment(17));
+        let mut backward = CandleQuery::new();
+        backward
+            .add_param("dailyAlignment", CandleQueryParam::DailyAlignment(17))
+            .add_param("smooth", CandleQueryParam::Smooth(false))
+            .add_param("price", CandleQueryParam::Price("M".to_string()))
+            .add_param("count", CandleQueryParam::Count(2))
+            .add_param("granularity", CandleQueryParam::Granularity(Granularity::S5));
+
+        let (mut first, mut second) = (client.clone(), client.clone());
+        let (a, b) = tokio::join!(
+            first.get_candles("EUR_USD", forward.build()),
+            second.get_candles("EUR_USD", backward.build()),
+        );
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(server.hits(Method::GET, "/v3/instruments/*/candles"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_errors_reach_every_waiter() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::error(404, None, "Account not found").delay(Duration::from_millis(50)));
+        let client = server.client_builder().coalesce_requests(true).build().unwrap();
+
+        let calls = (0..3).map(|_| {
+            let mut client = client.clone();
+            async move { client.get_account_summary().await }
+        });
+        for result in futures::future::join_all(calls).await {
+            assert!(matches!(result, Err(APIError::NotFound(_))), "{:?}", result);
+        }
+        assert_eq!(server.hits(Method::GET, "/v3/accounts/*/summary"), 1);
+
+        // Nothing listens on port 1, so the shared request fails before any response.
+        let refused = server
+            .client_builder()
+            .environment(Environment::Custom {
+                rest_url: "http://127.0.0.1:1".to_string(),
+                stream_url: "http://127.0.0.1:1".to_string(),
+            })
+            .retry_attempts(0)
+            .coalesce_requests(true)
+            .build()
+            .unwrap();
+        let calls = (0..3).map(|_| {
+            let mut client = refused.clone();
+            async move { client.get_accounts().await }
+        });
+        // The first call started the request and gets the original error, the others a copy.
+        let results = futures::future::join_all(calls).await;
+        assert!(matches!(results[0], Err(APIError::HTTP(_))), "{:?}", results[0]);
+        for result in &results[1..] {
+            assert!(matches!(result, Err(APIError::Other(_))), "{:?}", result);
+        }
+
+        // A call that nobody joined sees the same error it would without coalescing.
+        let mut alone = refused.clone();
+        assert!(matches!(alone.get_accounts().await, Err(APIError::HTTP(_))));
+    }
+
+    #[test]
+    fn test_copied_errors_keep_their_variant() {
+        let rate_limited = APIError::from_status(StatusCode::TOO_MANY_REQUESTS, br#"{"errorMessage": "Slow down"}"#);
+        let shared = SharedError::new(rate_limited.into());
+        for leader in [true, false] {
+            let error = APIError::from(shared.clone().into_error(leader));
+            assert!(matches!(&error, APIError::RateLimited(response) if response.error_message == "Slow down"), "{:?}", error);
+        }
+
+        let shared = SharedError::new(APIError::Timeout(Duration::from_secs(1)).into());
+        assert!(matches!(APIError::from(shared.into_error(false)), APIError::Timeout(_)));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_request_is_dropped() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/summary")
+            .respond_with(MockResponse::ok(fixtures::account_summary()).delay(Duration::from_secs(5)));
+        let client = server.client_builder().coalesce_requests(true).build().unwrap();
+
+        let mut hurried = client.clone().with_timeout(Duration::from_millis(50));
+        assert!(hurried.get_account_summary().await.unwrap_err().is_timeout());
+        let coalescer = client.coalescer().unwrap();
+        assert_eq!(coalescer.in_flight(), 0);
+    }
+}
@@ -0,0 +1,283 @@
+//! Separate request rates per kind of endpoint, and a rate for new connections.
+//!
+//! Classes are unlimited unless given a rate, so only the global `rate_limit` applies by default.
+//! A request to a limited class waits for a slot in its bucket before it queues for the shared
+//! buffer, concurrency limit and global `rate_limit`. A class over its share waits in the
+//! caller's task without holding any slot the other classes need, so a bulk candle download
+//! cannot hold up order placement.
+//!
+//! OANDA also limits how often new connections may be opened. Every new connection starts with
+//! a DNS lookup, so `ConnectionLimiter` enforces that rate as the client's resolver. Hosts given
+//! as IP addresses are not looked up and so are not limited.
+
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use tokio::time::sleep_until;
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+use crate::policies::adaptive_rate::AdaptiveRate;
+use crate::utils::clonable_request::ClonableRequest;
+
+
+/// OANDA asks for at most 2 new connections per second.
+pub const DEFAULT_CONNECTION_RATE_LIMIT: usize = 2;
+
+
+/// The kind of endpoint a request goes to, which picks its rate bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EndpointClass {
+    /// Market data: candles, pricing, order and position books.
+    Data,
+    /// Orders, trades and positions.
+    Trading,
+    /// Everything else: accounts, summaries, changes, configuration, instruments and transactions.
+    Account,
+}
+
+impl EndpointClass {
+    /// Classify a request path by its segments.
+    pub fn of(path: &str) -> EndpointClass {
+        let segments = || path.split(['/', '?']);
+        if segments().any(|s| matches!(s, "orders" | "trades" | "positions" | "openTrades" | "openPositions")) {
+            EndpointClass::Trading
+        } else if segments().any(|s| matches!(s, "candles" | "pricing" | "orderBook" | "positionBook")) {
+            EndpointClass::Data
+        } else {
+            EndpointClass::Account
+        }
+    }
+}
+
+
+/// Requests per second allowed for each `EndpointClass`, or `None` to leave the class to the
+/// global `rate_limit` alone. The global limit still caps their sum, so capping data below it
+/// leaves order placement room while a download runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EndpointRateLimits {
+    pub data: Option<usize>,
+    pub trading: Option<usize>,
+    pub account: Option<usize>,
+}
+
+impl EndpointRateLimits {
+    pub fn get(&self, class: EndpointClass) -> Option<usize> {
+        match class {
+            EndpointClass::Data => self.data,
+            EndpointClass::Trading => self.trading,
+            EndpointClass::Account => self.account,
+        }
+    }
+
+    pub fn set(&mut self, class: EndpointClass, rate_limit: usize) {
+        match class {
+            EndpointClass::Data => self.data = Some(rate_limit),
+            EndpointClass::Trading => self.trading = Some(rate_limit),
+            EndpointClass::Account => self.account = Some(rate_limit),
+        }
+    }
+
+    /// The classes that have a limit, with their limit.
+    pub fn iter(&self) -> impl Iterator<Item = (EndpointClass, usize)> + '_ {
+        [EndpointClass::Data, EndpointClass::Trading, EndpointClass::Account]
+            .into_iter()
+            .filter_map(|class| Some((class, self.get(class)?)))
+    }
+}
+
+
+/// One bucket per limited class, shared by a client and its clones.
+#[derive(Clone, Debug)]
+pub struct EndpointRates {
+    data: Option<AdaptiveRate>,
+    trading: Option<AdaptiveRate>,
+    account: Option<AdaptiveRate>,
+}
+
+impl EndpointRates {
+    pub fn new(limits: EndpointRateLimits) -> EndpointRates {
+        EndpointRates {
+            data: limits.data.map(AdaptiveRate::new),
+            trading: limits.trading.map(AdaptiveRate::new),
+            account: limits.account.map(AdaptiveRate::new),
+        }
+    }
+
+    pub fn get(&self, class: EndpointClass) -> Option<&AdaptiveRate> {
+        match class {
+            EndpointClass::Data => self.data.as_ref(),
+            EndpointClass::Trading => self.trading.as_ref(),
+            EndpointClass::Account => self.account.as_ref(),
+        }
+    }
+
+    pub fn layer(&self) -> EndpointLimitLayer {
+        EndpointLimitLayer { rates: self.clone() }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct EndpointLimitLayer {
+    rates: EndpointRates,
+}
+
+impl<S> Layer<S> for EndpointLimitLayer {
+    type Service = EndpointLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        EndpointLimitService {
+            inner,
+            rates: self.rates.clone(),
+        }
+    }
+}
+
+
+/// Waits for the request's class bucket, if its class has one, then for the inner service.
+#[derive(Clone, Debug)]
+pub struct EndpointLimitService<S> {
+    inner: S,
+    rates: EndpointRates,
+}
+
+impl<S> Service<ClonableRequest> for EndpointLimitService<S>
+where
+    S: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    /// Always ready: the inner service is only polled once the slot comes up, so nothing
+    /// behind it is held while the request waits.
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: ClonableRequest) -> Self::Future {
+        let start = self.rates.get(EndpointClass::of(&req.context.endpoint)).map(AdaptiveRate::reserve);
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            if let Some(start) = start {
+                sleep_until(start).await;
+            }
+            inner.oneshot(req).await.map_err(Into::into)
+        })
+    }
+}
+
+
+/// A DNS resolver that lets new connections start at most `rate` times per second.
+#[derive(Clone, Debug)]
+pub struct ConnectionLimiter {
+    rate: AdaptiveRate,
+}
+
+impl ConnectionLimiter {
+    pub fn new(rate_per_second: usize) -> ConnectionLimiter {
+        ConnectionLimiter {
+            rate: AdaptiveRate::new(rate_per_second),
+        }
+    }
+}
+
+impl Resolve for ConnectionLimiter {
+    fn resolve(&self, name: Name) -> Resolving {
+        let start = self.rate.reserve();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            sleep_until(start).await;
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use reqwest::Method;
+    use serde_json::json;
+    use tokio::time::{Duration, Instant};
+
+    use super::*;
+    use crate::environment::Environment;
+    use crate::mock::{MockResponse, MockServer, MOCK_ACCOUNT_ID};
+
+    #[test]
+    fn test_endpoint_classes() {
+        for (path, class) in [
+            ("/v3/instruments/EUR_USD/candles", EndpointClass::Data),
+            ("/v3/accounts/{accountID}/pricing", EndpointClass::Data),
+            ("/v3/instruments/EUR_USD/orderBook", EndpointClass::Data),
+            ("/v3/accounts/{accountID}/orders", EndpointClass::Trading),
+            ("/v3/accounts/{accountID}/trades/42/close", EndpointClass::Trading),
+            ("/v3/accounts/{accountID}/openPositions", EndpointClass::Trading),
+            ("/v3/accounts", EndpointClass::Account),
+            ("/v3/accounts/{accountID}/summary", EndpointClass::Account),
+            ("/v3/accounts/{accountID}/instruments", EndpointClass::Account),
+        ] {
+            assert_eq!(EndpointClass::of(path), class, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_data_does_not_hold_up_trading() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::POST, "/v3/accounts/*/orders")
+            .respond_with(MockResponse::ok(json!({})));
+        let client = server
+            .client_builder()
+            .concurrency_limit(2)
+            .endpoint_rate_limit(EndpointClass::Data, 2)
+            .build()
+            .unwrap();
+
+        let started = Instant::now();
+        let downloads = (0..6).map(|_| {
+            let mut client = client.clone();
+            async move { client.get_candles("EUR_USD", HashMap::new()).await }
+        });
+        let mut trader = client.clone();
+        let order = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let url = format!("/v3/accounts/{}/orders", MOCK_ACCOUNT_ID);
+            trader.post(&url, &json!({})).await.unwrap();
+            started.elapsed()
+        };
+
+        let (candles, order_done) = tokio::join!(futures::future::join_all(downloads), order);
+        assert!(candles.into_iter().all(|c| c.is_ok()));
+        // Two candle requests go straight away and the other four are spread over two seconds.
+        assert!(started.elapsed() >= Duration::from_millis(1900));
+        assert!(order_done < Duration::from_millis(500), "{:?}", order_done);
+    }
+
+    #[tokio::test]
+    async fn test_new_connections_limited() {
+        let server = MockServer::start().await.with_fixtures();
+        // A host name, so each new connection goes through the resolver.
+        let url = server.url().replace("127.0.0.1", "localhost");
+        let mut client = server
+            .client_builder()
+            .environment(Environment::Custom { rest_url: url.clone(), stream_url: url })
+            .pool_max_idle_per_host(0)
+            .connection_rate_limit(1)
+            .build()
+            .unwrap();
+
+        let started = Instant::now();
+        client.get_accounts().await.unwrap();
+        client.get_accounts().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+}
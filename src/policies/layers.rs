@@ -0,0 +1,180 @@
+use std::fmt;
+use std::sync::Arc;
+
+use tower::util::BoxCloneService;
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+use crate::utils::clonable_request::ClonableRequest;
+
+
+/// The type-erased service a `UserLayer` wraps and returns.
+pub type BoxRequestService = BoxCloneService<ClonableRequest, reqwest::Response, BoxError>;
+
+
+/// Where a user-supplied layer goes in the stack `RateLimiter` builds:
+///
+/// ```text
+/// coalesce -> BeforeRateLimit -> endpoint_limits -> priority_scheduler -> buffer -> BeforeRetry -> retry -> AfterRetry -> circuit_breaker -> rate_limit -> AfterRateLimit -> ClientWrapper
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StackPosition {
+    /// In the caller's task, before the request waits for its endpoint bucket and a
+    /// concurrency slot. Runs once per call, or once per shared request when
+    /// `coalesce_requests` is on.
+    BeforeRateLimit,
+    /// After the call got a concurrency slot, around all of its retries. Runs once per call.
+    BeforeRetry,
+    /// Inside the retries, before the circuit breaker and the rate limit. Runs for every
+    /// attempt, so this is the place for fault injection.
+    AfterRetry,
+    /// After the attempt waited for the rate limit, right above `ClientWrapper`. Runs for every
+    /// attempt just before it is sent, so this is the place for request signing or auth refresh.
+    AfterRateLimit,
+}
+
+
+/// A tower layer supplied through `OandaClientBuilder::layer`.
+#[derive(Clone)]
+pub struct UserLayer(Arc<dyn Fn(BoxRequestService) -> BoxRequestService + Send + Sync>);
+
+impl UserLayer {
+    pub fn new<L>(layer: L) -> UserLayer
+    where
+        L: Layer<BoxRequestService> + Send + Sync + 'static,
+        L::Service: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
+        <L::Service as Service<ClonableRequest>>::Error: Into<BoxError>,
+        <L::Service as Service<ClonableRequest>>::Future: Send + 'static,
+    {
+        UserLayer(Arc::new(move |service| {
+            BoxCloneService::new(layer.layer(service).map_err(Into::into))
+        }))
+    }
+}
+
+impl fmt::Debug for UserLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UserLayer")
+    }
+}
+
+
+/// The user layers for one `StackPosition`, in the order they were added; the first is outermost.
+#[derive(Clone, Debug, Default)]
+pub struct UserLayers(Vec<UserLayer>);
+
+impl UserLayers {
+    pub fn at(layers: &[(StackPosition, UserLayer)], position: StackPosition) -> UserLayers {
+        UserLayers(
+            layers
+                .iter()
+                .filter(|(p, _)| *p == position)
+                .map(|(_, layer)| layer.clone())
+                .collect(),
+        )
+    }
+}
+
+impl<S> Layer<S> for UserLayers
+where
+    S: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Service = BoxRequestService;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let inner = BoxCloneService::new(inner.map_err(Into::into));
+        self.0.iter().rev().fold(inner, |service, layer| (layer.0)(service))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+
+    use futures::future::BoxFuture;
+    use reqwest::header::HeaderValue;
+    use reqwest::Method;
+    use tower::layer::layer_fn;
+    use tower::util::MapRequestLayer;
+
+    use super::*;
+    use crate::error::APIError;
+    use crate::mock::MockServer;
+
+    /// Fails the first `remaining` calls without sending them.
+    #[derive(Clone)]
+    struct FailFirst<S> {
+        inner: S,
+        remaining: Arc<AtomicUsize>,
+    }
+
+    impl<S> Service<ClonableRequest> for FailFirst<S>
+    where
+        S: Service<ClonableRequest, Response = reqwest::Response, Error = BoxError>,
+        S::Future: Send + 'static,
+    {
+        type Response = reqwest::Response;
+        type Error = BoxError;
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, req: ClonableRequest) -> Self::Future {
+            let fail = self
+                .remaining
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if fail {
+                Box::pin(async { Err(APIError::Other("injected fault".to_string()).into()) })
+            } else {
+                Box::pin(self.inner.call(req))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_layers() {
+        let server = MockServer::start().await.with_fixtures();
+        let remaining = Arc::new(AtomicUsize::new(1));
+        let fault = remaining.clone();
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = sent.clone();
+
+        let mut client = server
+            .client_builder()
+            .retry_attempts(1)
+            .layer(
+                StackPosition::BeforeRateLimit,
+                MapRequestLayer::new(|mut req: ClonableRequest| {
+                    req.headers_mut().insert("X-Signature", HeaderValue::from_static("signed"));
+                    req
+                }),
+            )
+            .layer(
+                StackPosition::AfterRetry,
+                layer_fn(move |inner| FailFirst { inner, remaining: fault.clone() }),
+            )
+            .layer(
+                StackPosition::AfterRateLimit,
+                MapRequestLayer::new(move |req: ClonableRequest| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    req
+                }),
+            )
+            .build()
+            .unwrap();
+
+        // The injected fault is retried, so the call still succeeds; the failed attempt never
+        // got past the fault to the layer after the rate limit.
+        client.get_accounts().await.unwrap();
+        assert_eq!(remaining.load(Ordering::SeqCst), 0);
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(server.hits(Method::GET, "/v3/accounts"), 1);
+        assert_eq!(server.received_requests()[0].header("X-Signature"), Some("signed"));
+    }
+}
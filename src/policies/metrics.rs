@@ -0,0 +1,392 @@
+//! Metrics for the request stack, reported through the `metrics` facade.
+//!
+//! Nothing is exported by this crate itself: install a recorder, such as
+//! `metrics-exporter-prometheus`, to scrape them.
+//!
+//! | name | type | labels |
+//! |------|------|--------|
+//! | `oanda_requests_total` | counter | `method`, `endpoint`, `status` |
+//! | `oanda_request_duration_seconds` | histogram | `method`, `endpoint` |
+//! | `oanda_retries_total` | counter | `method`, `endpoint` |
+//! | `oanda_queue_wait_seconds` | histogram | |
+//! | `oanda_rate_limit_wait_seconds` | histogram | |
+//! | `oanda_buffer_queued` | gauge | |
+//! | `oanda_in_flight` | gauge | |
+//! | `oanda_concurrency_saturation` | gauge | |
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+use crate::utils::clonable_request::ClonableRequest;
+
+
+pub const REQUESTS_TOTAL: &str = "oanda_requests_total";
+pub const REQUEST_DURATION_SECONDS: &str = "oanda_request_duration_seconds";
+pub const RETRIES_TOTAL: &str = "oanda_retries_total";
+pub const QUEUE_WAIT_SECONDS: &str = "oanda_queue_wait_seconds";
+pub const RATE_LIMIT_WAIT_SECONDS: &str = "oanda_rate_limit_wait_seconds";
+pub const BUFFER_QUEUED: &str = "oanda_buffer_queued";
+pub const IN_FLIGHT: &str = "oanda_in_flight";
+pub const CONCURRENCY_SATURATION: &str = "oanda_concurrency_saturation";
+
+
+/// Where a `MetricsLayer` sits in the stack `RateLimiter` builds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// In front of the buffer: the request is queued.
+    Enqueue,
+    /// Below the concurrency limit: the request holds one of its slots.
+    Admission,
+    /// Right above `ClientWrapper`, below the rate limiter and the retries: one HTTP exchange.
+    Dispatch,
+}
+
+
+/// Counts shared by the layers of one stack.
+#[derive(Debug)]
+pub struct Metrics {
+    concurrency_limit: usize,
+    in_flight: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn new(concurrency_limit: usize) -> Arc<Metrics> {
+        describe_counter!(REQUESTS_TOTAL, "HTTP requests sent to OANDA, by endpoint and status.");
+        describe_histogram!(REQUEST_DURATION_SECONDS, Unit::Seconds, "Time until OANDA's response headers arrived.");
+        describe_counter!(RETRIES_TOTAL, "Requests sent again after a failure.");
+        describe_histogram!(QUEUE_WAIT_SECONDS, Unit::Seconds, "Time spent in the buffer and waiting for a concurrency slot.");
+        describe_histogram!(RATE_LIMIT_WAIT_SECONDS, Unit::Seconds, "Time spent waiting on the rate limiter.");
+        describe_gauge!(BUFFER_QUEUED, "Requests waiting in the buffer.");
+        describe_gauge!(IN_FLIGHT, "Requests holding a concurrency slot.");
+        describe_gauge!(CONCURRENCY_SATURATION, "Share of the concurrency limit in use, from 0 to 1.");
+
+        Arc::new(Metrics {
+            concurrency_limit,
+            in_flight: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn layer(self: &Arc<Self>, stage: Stage) -> MetricsLayer {
+        MetricsLayer {
+            metrics: self.clone(),
+            stage,
+        }
+    }
+
+    fn set_in_flight(&self, in_flight: usize) {
+        gauge!(CONCURRENCY_SATURATION).set(in_flight as f64 / self.concurrency_limit.max(1) as f64);
+    }
+}
+
+
+/// Counts a request as queued until it is admitted, or until its call is dropped.
+struct Queued(Arc<AtomicBool>);
+
+impl Queued {
+    fn new() -> Queued {
+        gauge!(BUFFER_QUEUED).increment(1.0);
+        Queued(Arc::new(AtomicBool::new(true)))
+    }
+
+    /// Take the request off the gauge, once.
+    fn dequeue(flag: &AtomicBool) {
+        if flag.swap(false, Ordering::SeqCst) {
+            gauge!(BUFFER_QUEUED).decrement(1.0);
+        }
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        Queued::dequeue(&self.0);
+    }
+}
+
+
+/// Counts a request as in flight until its call finishes or is dropped.
+struct InFlight(Arc<Metrics>);
+
+impl InFlight {
+    fn new(metrics: Arc<Metrics>) -> InFlight {
+        gauge!(IN_FLIGHT).increment(1.0);
+        let in_flight = metrics.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        metrics.set_in_flight(in_flight);
+        InFlight(metrics)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        gauge!(IN_FLIGHT).decrement(1.0);
+        let in_flight = self.0.in_flight.fetch_sub(1, Ordering::SeqCst) - 1;
+        self.0.set_in_flight(in_flight);
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+    stage: Stage,
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+            stage: self.stage,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+    stage: Stage,
+}
+
+impl<S> Service<ClonableRequest> for MetricsService<S>
+where
+    S: Service<ClonableRequest, Response = reqwest::Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ClonableRequest) -> Self::Future {
+        match self.stage {
+            // The gauges are brought down by guards held in the future, so a call dropped on a
+            // timeout, in a queue or mid-request still comes off them.
+            Stage::Enqueue => {
+                let queued = Queued::new();
+                req.context.queued = Some(queued.0.clone());
+                let future = self.inner.call(req);
+                Box::pin(async move {
+                    let _queued = queued;
+                    future.await
+                })
+            }
+            Stage::Admission => {
+                let now = Instant::now();
+                if let Some(queued) = &req.context.queued {
+                    Queued::dequeue(queued);
+                }
+                histogram!(QUEUE_WAIT_SECONDS).record(now.saturating_duration_since(req.context.enqueued_at));
+                req.context.admitted_at = Some(now);
+
+                let in_flight = InFlight::new(self.metrics.clone());
+                let future = self.inner.call(req);
+                Box::pin(async move {
+                    let _in_flight = in_flight;
+                    future.await
+                })
+            }
+            Stage::Dispatch => {
+                let now = Instant::now();
+                let method = req.method().to_string();
+                let endpoint = req.context.endpoint.clone();
+                if req.context.attempt > 1 {
+                    counter!(RETRIES_TOTAL, "method" => method.clone(), "endpoint" => endpoint.clone()).increment(1);
+                } else if let Some(admitted_at) = req.context.admitted_at {
+                    histogram!(RATE_LIMIT_WAIT_SECONDS).record(now.saturating_duration_since(admitted_at));
+                }
+
+                let future = self.inner.call(req);
+                Box::pin(async move {
+                    let result = future.await;
+                    let status = match &result {
+                        Ok(response) => response.status().as_u16().to_string(),
+                        Err(_) => "error".to_string(),
+                    };
+                    histogram!(REQUEST_DURATION_SECONDS, "method" => method.clone(), "endpoint" => endpoint.clone())
+                        .record(now.elapsed());
+                    counter!(REQUESTS_TOTAL, "method" => method, "endpoint" => endpoint, "status" => status).increment(1);
+                    result
+                })
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString};
+    use reqwest::Method;
+
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    /// Keeps the last value of every metric, keyed by name and labels.
+    #[derive(Default)]
+    pub(crate) struct TestRecorder {
+        values: Arc<Mutex<HashMap<String, f64>>>,
+    }
+
+    struct Handle {
+        key: String,
+        values: Arc<Mutex<HashMap<String, f64>>>,
+    }
+
+    impl Handle {
+        fn update(&self, f: impl FnOnce(f64) -> f64) {
+            let mut values = self.values.lock().unwrap();
+            let value = values.entry(self.key.clone()).or_default();
+            *value = f(*value);
+        }
+    }
+
+    impl CounterFn for Handle {
+        fn increment(&self, value: u64) {
+            self.update(|v| v + value as f64);
+        }
+
+        fn absolute(&self, value: u64) {
+            self.update(|_| value as f64);
+        }
+    }
+
+    impl GaugeFn for Handle {
+        fn increment(&self, value: f64) {
+            self.update(|v| v + value);
+        }
+
+        fn decrement(&self, value: f64) {
+            self.update(|v| v - value);
+        }
+
+        fn set(&self, value: f64) {
+            self.update(|_| value);
+        }
+    }
+
+    /// Histograms keep the number of samples.
+    impl HistogramFn for Handle {
+        fn record(&self, _value: f64) {
+            self.update(|v| v + 1.0);
+        }
+    }
+
+    impl TestRecorder {
+        fn handle(&self, key: &Key) -> Arc<Handle> {
+            let mut labels: Vec<String> = key.labels().map(|l| format!("{}={}", l.key(), l.value())).collect();
+            labels.sort();
+            Arc::new(Handle {
+                key: format!("{}{{{}}}", key.name(), labels.join(",")),
+                values: self.values.clone(),
+            })
+        }
+
+        pub(crate) fn get(&self, key: &str) -> f64 {
+            self.values.lock().unwrap().get(key).copied().unwrap_or_default()
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.handle(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.handle(key))
+        }
+
+        fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(self.handle(key))
+        }
+    }
+
+    #[test]
+    fn test_metrics_per_endpoint_and_status() {
+        let recorder = TestRecorder::default();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        // The recorder is local to this thread, so the whole client has to run on it.
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let server = MockServer::start().await.with_fixtures();
+                server
+                    .mock(Method::GET, "/v3/accounts/*/instruments")
+                    .respond_with(MockResponse::error(404, None, "Not found"));
+                let mut client = server.client();
+
+                client.get_account_summary().await.unwrap();
+                client.get_account_summary().await.unwrap();
+                client.get_account_instruments().await.unwrap_err();
+            })
+        });
+
+        assert_eq!(
+            recorder.get("oanda_requests_total{endpoint=/v3/accounts/{accountID}/summary,method=GET,status=200}"),
+            2.0
+        );
+        assert_eq!(
+            recorder.get("oanda_requests_total{endpoint=/v3/accounts/{accountID}/instruments,method=GET,status=404}"),
+            1.0
+        );
+        assert_eq!(
+            recorder.get("oanda_request_duration_seconds{endpoint=/v3/accounts/{accountID}/summary,method=GET}"),
+            2.0
+        );
+        assert_eq!(recorder.get("oanda_queue_wait_seconds{}"), 3.0);
+        assert_eq!(recorder.get("oanda_rate_limit_wait_seconds{}"), 3.0);
+        assert_eq!(recorder.get("oanda_buffer_queued{}"), 0.0);
+        assert_eq!(recorder.get("oanda_in_flight{}"), 0.0);
+        assert_eq!(recorder.get("oanda_concurrency_saturation{}"), 0.0);
+    }
+
+    #[test]
+    fn test_gauges_recover_from_dropped_calls() {
+        use std::time::Duration;
+        use crate::mock::fixtures;
+
+        let recorder = TestRecorder::default();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let server = MockServer::start().await.with_fixtures();
+                server
+                    .mock(Method::GET, "/v3/accounts/*/summary")
+                    .respond_with(MockResponse::ok(fixtures::account_summary()).delay(Duration::from_secs(5)));
+                let client = server.client_builder().concurrency_limit(1).build().unwrap();
+
+                // The first call times out in flight, the second while it waits for the slot.
+                let mut in_flight = client.clone().with_timeout(Duration::from_millis(100));
+                let mut queued = client.clone().with_timeout(Duration::from_millis(50));
+                let (first, second) = tokio::join!(in_flight.get_account_summary(), queued.get_account_summary());
+                assert!(first.unwrap_err().is_timeout());
+                assert!(second.unwrap_err().is_timeout());
+            })
+        });
+
+        assert_eq!(recorder.get("oanda_buffer_queued{}"), 0.0);
+        assert_eq!(recorder.get("oanda_in_flight{}"), 0.0);
+        assert_eq!(recorder.get("oanda_concurrency_saturation{}"), 0.0);
+    }
+}
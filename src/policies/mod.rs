@@ -1,2 +1,10 @@
+pub mod adaptive_rate;
+pub mod circuit_breaker;
+pub mod coalesce;
+pub mod endpoint_limits;
+pub mod priority;
+pub mod layers;
 pub mod retry_policy;
-pub mod rate_limiter;
\ No newline at end of file
+pub mod rate_limiter;
+#[cfg(feature = "metrics")]
+pub mod metrics;
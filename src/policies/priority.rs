@@ -0,0 +1,425 @@
+//! Serves urgent requests first when more are waiting than the concurrency limit lets through.
+//!
+//! Every request carries a `Priority`. Trading endpoints default to `Critical` and everything
+//! else to `Normal`; `OandaClient::with_priority` overrides that for the calls made through one
+//! client, so a candle backfill can run as `Bulk` while closing a trade still goes first.
+//!
+//! The `PriorityScheduler` hands out the `concurrency_limit` slots. When a slot frees up it goes
+//! to the waiting request with the highest priority. A request gains one level for every `aging`
+//! it has waited, so a `Bulk` request waits at most twice `aging` before it is served like a
+//! `Critical` one, and among equals the one waiting longest goes first.
+//!
+//! At most `buffer_size` requests wait in the queues. Once they are full the scheduler is not
+//! ready, so further calls wait in their own task, in arrival order, until a queued request gets
+//! its slot or gives up.
+//!
+//! ```no_run
+//! # async fn run(client: oanda_rs::client::OandaClient) -> Result<(), oanda_rs::error::APIError> {
+//! use std::collections::HashMap;
+//! use oanda_rs::policies::priority::Priority;
+//!
+//! let mut backfill = client.clone().with_priority(Priority::Bulk);
+//! let candles = backfill.get_candles("EUR_USD", HashMap::new()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+
+use futures::future::BoxFuture;
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+use crate::policies::endpoint_limits::EndpointClass;
+use crate::utils::clonable_request::ClonableRequest;
+
+
+/// How long a waiting request takes to gain one priority level.
+pub const DEFAULT_PRIORITY_AGING: Duration = Duration::from_secs(1);
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Large jobs that can wait, such as history downloads.
+    Bulk,
+    #[default]
+    Normal,
+    /// Calls that must not wait behind others, such as closing a trade or cancelling an order.
+    Critical,
+}
+
+impl Priority {
+    /// The priority a request gets unless the client sets one.
+    pub fn for_endpoint(path: &str) -> Priority {
+        match EndpointClass::of(path) {
+            EndpointClass::Trading => Priority::Critical,
+            EndpointClass::Data | EndpointClass::Account => Priority::Normal,
+        }
+    }
+
+    fn level(self) -> u32 {
+        self as u32
+    }
+}
+
+
+#[derive(Debug)]
+struct Waiter {
+    since: Instant,
+    slot: oneshot::Sender<Slot>,
+}
+
+#[derive(Debug)]
+struct State {
+    available: usize,
+    /// One queue per priority, indexed by `Priority::level`.
+    queues: [VecDeque<Waiter>; 3],
+    /// Requests let in by `poll_ready` that have no slot yet, at most `queue_limit`.
+    admitted: usize,
+    queue_limit: usize,
+    /// Tasks waiting for room in the queues.
+    blocked: Vec<Waker>,
+}
+
+
+/// The concurrency slots shared by a client and its clones.
+#[derive(Clone, Debug)]
+pub struct PriorityScheduler {
+    state: Arc<Mutex<State>>,
+    aging: Duration,
+}
+
+impl PriorityScheduler {
+    pub fn new(concurrency_limit: usize, queue_limit: usize, aging: Duration) -> PriorityScheduler {
+        PriorityScheduler {
+            state: Arc::new(Mutex::new(State {
+                available: concurrency_limit.max(1),
+                queues: Default::default(),
+                admitted: 0,
+                queue_limit: queue_limit.max(1),
+                blocked: Vec::new(),
+            })),
+            aging,
+        }
+    }
+
+    pub fn layer(&self) -> PrioritySchedulerLayer {
+        PrioritySchedulerLayer { scheduler: self.clone() }
+    }
+
+    /// Requests waiting for a slot.
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Make room for one more request, or register the task to be woken when there is some.
+    fn poll_admit(&self, cx: &mut Context<'_>) -> Poll<Admission> {
+        let mut state = self.state.lock().unwrap();
+        if state.admitted < state.queue_limit {
+            state.admitted += 1;
+            Poll::Ready(Admission { scheduler: Some(self.clone()) })
+        } else {
+            state.blocked.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// An admitted request got its slot or gave up; wake the tasks waiting for room.
+    fn leave(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.admitted -= 1;
+        // All of them, since a woken task may have gone away in the meantime.
+        for waker in state.blocked.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Wait for a slot. Dropping the future gives up the place in the queue.
+    async fn acquire(&self, priority: Priority) -> Slot {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 && state.queues.iter().all(VecDeque::is_empty) {
+                state.available -= 1;
+                return Slot { scheduler: Some(self.clone()) };
+            }
+            let (slot, receiver) = oneshot::channel();
+            state.queues[priority.level() as usize].push_back(Waiter { since: Instant::now(), slot });
+            receiver
+        };
+        // The sender is only dropped after a slot was sent, so this cannot fail.
+        receiver.await.expect("priority scheduler dropped a waiter")
+    }
+
+    /// Hand a freed slot to the waiter that should go next, or put it back.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        while let Some(index) = self.next(&state.queues, now) {
+            let waiter = state.queues[index].pop_front().unwrap();
+            if let Err(mut slot) = waiter.slot.send(Slot { scheduler: Some(self.clone()) }) {
+                // The caller gave up waiting; the slot is still ours to give.
+                slot.scheduler = None;
+                continue;
+            }
+            return;
+        }
+        state.available += 1;
+    }
+
+    /// The queue whose first waiter has the highest priority after aging.
+    fn next(&self, queues: &[VecDeque<Waiter>; 3], now: Instant) -> Option<usize> {
+        let critical = Priority::Critical.level();
+        queues
+            .iter()
+            .enumerate()
+            .filter_map(|(level, queue)| queue.front().map(|waiter| (level, waiter.since)))
+            .max_by_key(|&(level, since)| {
+                let waited = now.saturating_duration_since(since);
+                let aged = if self.aging.is_zero() {
+                    critical
+                } else {
+                    (waited.as_nanos() / self.aging.as_nanos()).min(critical as u128) as u32
+                };
+                // Oldest first among equals.
+                ((level as u32 + aged).min(critical), std::cmp::Reverse(since))
+            })
+            .map(|(level, _)| level)
+    }
+}
+
+
+/// Room in the queues, given back once the request has its slot or on drop.
+#[derive(Debug)]
+struct Admission {
+    scheduler: Option<PriorityScheduler>,
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.leave();
+        }
+    }
+}
+
+
+/// A concurrency slot, given back to the scheduler on drop.
+#[derive(Debug)]
+struct Slot {
+    scheduler: Option<PriorityScheduler>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release();
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct PrioritySchedulerLayer {
+    scheduler: PriorityScheduler,
+}
+
+impl<S> Layer<S> for PrioritySchedulerLayer {
+    type Service = PrioritySchedulerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PrioritySchedulerService {
+            inner,
+            scheduler: self.scheduler.clone(),
+            admission: None,
+        }
+    }
+}
+
+
+/// Waits for a slot in the order of `Priority`, then for the inner service, and holds the slot
+/// until the response (retries included) is back.
+#[derive(Debug)]
+pub struct PrioritySchedulerService<S> {
+    inner: S,
+    scheduler: PriorityScheduler,
+    /// The room taken by `poll_ready`, used by the next `call`.
+    admission: Option<Admission>,
+}
+
+impl<S: Clone> Clone for PrioritySchedulerService<S> {
+    fn clone(&self) -> Self {
+        PrioritySchedulerService {
+            inner: self.inner.clone(),
+            scheduler: self.scheduler.clone(),
+            admission: None,
+        }
+    }
+}
+
+impl<S> Service<ClonableRequest> for PrioritySchedulerService<S>
+where
+    S: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    /// Ready while the queues have room: requests wait for their slot in the caller's task,
+    /// where they can be ordered, instead of in the buffer's queue.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.admission.is_none() {
+            self.admission = Some(ready!(self.scheduler.poll_admit(cx)));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: ClonableRequest) -> Self::Future {
+        let admission = self.admission.take().expect("poll_ready must be called before call");
+        let scheduler = self.scheduler.clone();
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let _slot = scheduler.acquire(req.context.priority).await;
+            drop(admission);
+            inner.oneshot(req).await.map_err(Into::into)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use reqwest::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{fixtures, MockResponse, MockServer, MOCK_ACCOUNT_ID};
+
+    /// Queue one waiter per priority behind a held slot and return the order they are served in.
+    async fn served_order(scheduler: &PriorityScheduler, arrivals: &[(Priority, Duration)]) -> Vec<Priority> {
+        let held = scheduler.acquire(Priority::Normal).await;
+        let (order, mut served) = tokio::sync::mpsc::unbounded_channel();
+        for &(priority, delay) in arrivals {
+            tokio::time::advance(delay).await;
+            let (scheduler, order) = (scheduler.clone(), order.clone());
+            tokio::spawn(async move {
+                let _slot = scheduler.acquire(priority).await;
+                order.send(priority).unwrap();
+            });
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(scheduler.waiting(), arrivals.len());
+        drop(order);
+        drop(held);
+
+        let mut result = Vec::new();
+        while let Some(priority) = served.recv().await {
+            result.push(priority);
+        }
+        result
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_higher_priority_first() {
+        let scheduler = PriorityScheduler::new(1, 10, Duration::from_secs(10));
+        let order = served_order(&scheduler, &[
+            (Priority::Bulk, Duration::ZERO),
+            (Priority::Normal, Duration::from_millis(1)),
+            (Priority::Bulk, Duration::from_millis(1)),
+            (Priority::Critical, Duration::from_millis(1)),
+        ]).await;
+        assert_eq!(order, [Priority::Critical, Priority::Normal, Priority::Bulk, Priority::Bulk]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiting_requests_age() {
+        let scheduler = PriorityScheduler::new(1, 10, Duration::from_secs(1));
+        // After two seconds the bulk request counts as critical and has waited longest.
+        let order = served_order(&scheduler, &[
+            (Priority::Bulk, Duration::ZERO),
+            (Priority::Critical, Duration::from_secs(2)),
+            (Priority::Normal, Duration::ZERO),
+        ]).await;
+        assert_eq!(order, [Priority::Bulk, Priority::Critical, Priority::Normal]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abandoned_waiter_passes_slot_on() {
+        let scheduler = PriorityScheduler::new(1, 10, DEFAULT_PRIORITY_AGING);
+        let held = scheduler.acquire(Priority::Normal).await;
+        let abandoned = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire(Priority::Critical).await }
+        });
+        tokio::task::yield_now().await;
+        abandoned.abort();
+        let _ = abandoned.await;
+
+        drop(held);
+        let _slot = scheduler.acquire(Priority::Bulk).await;
+        assert_eq!(scheduler.waiting(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_queues_apply_backpressure() {
+        let scheduler = PriorityScheduler::new(1, 2, DEFAULT_PRIORITY_AGING);
+        let held = scheduler.acquire(Priority::Normal).await;
+        let service = scheduler.layer().layer(tower::service_fn(|_req: ClonableRequest| async {
+            Ok::<_, BoxError>(reqwest::Response::from(http::Response::new("")))
+        }));
+        let request = || ClonableRequest::new(reqwest::Request::new(Method::GET, "http://localhost/v3/accounts".parse().unwrap()));
+
+        let queued: Vec<_> = (0..2).map(|_| tokio::spawn(service.clone().oneshot(request()))).collect();
+        tokio::task::yield_now().await;
+        assert_eq!(scheduler.waiting(), 2);
+
+        // The queues are full, so a third request is held back before it joins them.
+        let mut third = service.clone();
+        assert!(futures::poll!(std::future::poll_fn(|cx| third.poll_ready(cx))).is_pending());
+        assert_eq!(scheduler.waiting(), 2);
+
+        drop(held);
+        for call in queued {
+            call.await.unwrap().unwrap();
+        }
+        third.ready().await.unwrap().call(request()).await.unwrap();
+        assert_eq!(scheduler.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_trading_skips_queued_candles() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/instruments/*/candles")
+            .respond_with(MockResponse::ok(fixtures::candles("EUR_USD", "S5", 2)).delay(Duration::from_millis(100)));
+        server
+            .mock(Method::PUT, "/v3/accounts/*/trades/*/close")
+            .respond_with(MockResponse::ok(json!({})));
+        let client = server.client_builder().concurrency_limit(1).build().unwrap();
+
+        let started = Instant::now();
+        let downloads = (0..8).map(|_| {
+            let mut client = client.clone().with_priority(Priority::Bulk);
+            async move { client.get_candles("EUR_USD", HashMap::new()).await }
+        });
+        let mut trader = client.clone();
+        let close = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let url = format!("/v3/accounts/{}/trades/42/close", MOCK_ACCOUNT_ID);
+            trader.put(&url, &json!({})).await.unwrap();
+            started.elapsed()
+        };
+
+        let (candles, closed) = tokio::join!(futures::future::join_all(downloads), close);
+        assert!(candles.into_iter().all(|c| c.is_ok()));
+        // The close only waits for the candle request already in flight, not the seven queued.
+        assert!(closed < Duration::from_millis(400), "{:?}", closed);
+        assert!(started.elapsed() >= Duration::from_millis(800));
+    }
+}
@@ -1,28 +1,62 @@
-use tokio::time::Duration;
 use tower::{Service, ServiceBuilder};
 use tower::util::BoxCloneService;
 
 use std::error::Error as StdError;
+use std::time::Duration;
 
 use crate::utils::clonable_request::ClonableRequest;
 use crate::error::APIError;
+use crate::policies::adaptive_rate::AdaptiveRate;
+use crate::policies::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use crate::policies::coalesce::Coalescer;
+use crate::policies::endpoint_limits::{EndpointRateLimits, EndpointRates};
+use crate::policies::layers::{StackPosition, UserLayer, UserLayers};
+use crate::policies::priority::{PriorityScheduler, DEFAULT_PRIORITY_AGING};
 use crate::policies::retry_policy::RetryPolicy;
+#[cfg(feature = "metrics")]
+use crate::policies::metrics::{Metrics, Stage};
+#[cfg(not(feature = "metrics"))]
+use tower::layer::util::Identity;
+
+
+/// The settings `RateLimiter::build` assembles the stack from.
+#[derive(Clone, Debug)]
+pub struct StackConfig {
+    pub rate_limit: usize,
+    pub buffer_size: usize,
+    pub concurrency_limit: usize,
+    pub priority_aging: Duration,
+    pub retry_policy: RetryPolicy,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub coalesce: bool,
+    pub endpoint_limits: EndpointRateLimits,
+    pub layers: Vec<(StackPosition, UserLayer)>,
+}
 
 
 #[derive(Clone, Debug)]
 pub struct RateLimiter<S>
 where
-    S: Service<ClonableRequest> + Clone + Send + 'static,
+    S: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
     S::Response: Send + 'static,
     S::Error: Into<APIError> + Into<Box<dyn StdError + Send + Sync + 'static>> + Send + Sync + 'static,
     S::Future: Send + 'static,
 {
     pub service: BoxCloneService<ClonableRequest, <S as Service<ClonableRequest>>::Response, Box<dyn StdError + Send + Sync>>,
+    /// The request rate, shared by every clone and lowered for a while after a 429.
+    pub rate: AdaptiveRate,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// The identical GETs in flight, when coalescing is on.
+    pub coalescer: Option<Coalescer>,
+    /// The per-class buckets requests wait on before they queue.
+    pub endpoint_rates: EndpointRates,
+    /// Hands out the concurrency slots by request priority.
+    pub scheduler: PriorityScheduler,
 }
 
 impl<S> RateLimiter<S>
 where
-    S: Service<ClonableRequest> + Clone + Send + 'static,
+    S: Service<ClonableRequest, Response = reqwest::Response> + Clone + Send + 'static,
     S::Response: Send + 'static,
     S::Error: Into<APIError> + Into<Box<dyn StdError + Send + Sync + 'static>> + Send + Sync + 'static,
     S::Future: Send + 'static,
@@ -34,23 +68,87 @@ where
         concurrency_limit: usize, 
         retry_attempts: usize
     ) -> Result<Self, APIError> {
+        Self::with_layers(service, rate_limit, buffer_size, concurrency_limit, RetryPolicy::new(retry_attempts), &[])
+    }
+
+    /// Like `new`, with a configured retry policy and user layers inserted at their `StackPosition`s.
+    pub fn with_layers(
+        service: S,
+        rate_limit: usize,
+        buffer_size: usize,
+        concurrency_limit: usize,
+        retry_policy: RetryPolicy,
+        layers: &[(StackPosition, UserLayer)],
+    ) -> Result<Self, APIError> {
+        Self::build(service, StackConfig {
+            rate_limit,
+            buffer_size,
+            concurrency_limit,
+            priority_aging: DEFAULT_PRIORITY_AGING,
+            retry_policy,
+            circuit_breaker: None,
+            coalesce: false,
+            endpoint_limits: EndpointRateLimits::default(),
+            layers: layers.to_vec(),
+        })
+    }
+
+    /// Build the whole stack, including the optional circuit breaker below the retries, and the
+    /// optional coalescing, per-class rate limits and priority scheduler in front of the buffer.
+    pub fn build(service: S, config: StackConfig) -> Result<Self, APIError> {
+        let StackConfig { rate_limit, buffer_size, concurrency_limit, priority_aging, retry_policy, circuit_breaker, coalesce, endpoint_limits, layers } = config;
+        let layers = layers.as_slice();
 
-        let rate_limit_u64: u64 = rate_limit
-            .try_into()
-            .map_err(|_| APIError::Other("Invalid rate limit value".to_string()))?;
+        if rate_limit == 0 {
+            return Err(APIError::Other("Invalid rate limit value".to_string()));
+        }
+        let rate = AdaptiveRate::new(rate_limit);
+        let endpoint_rates = EndpointRates::new(endpoint_limits);
+        let circuit_breaker = circuit_breaker.map(CircuitBreaker::new);
+        let scheduler = PriorityScheduler::new(concurrency_limit, buffer_size, priority_aging);
+        let coalescer = coalesce.then(Coalescer::new);
 
-        let retry_policy = RetryPolicy { attempts: retry_attempts };
+        #[cfg(feature = "metrics")]
+        let (enqueue_metrics, admission_metrics, dispatch_metrics) = {
+            let metrics = Metrics::new(concurrency_limit);
+            (
+                Some(metrics.layer(Stage::Enqueue)),
+                Some(metrics.layer(Stage::Admission)),
+                Some(metrics.layer(Stage::Dispatch)),
+            )
+        };
+        #[cfg(not(feature = "metrics"))]
+        let (enqueue_metrics, admission_metrics, dispatch_metrics) =
+            (None::<Identity>, None::<Identity>, None::<Identity>);
 
         let rate_limited_service: BoxCloneService<ClonableRequest, <S as Service<ClonableRequest>>::Response, Box<dyn StdError + Send + Sync>> = ServiceBuilder::new()
             .boxed_clone()
+            // Above the enqueue metric: a request that joins another is never queued.
+            .option_layer(coalescer.as_ref().map(Coalescer::layer))
+            .option_layer(enqueue_metrics)
+            .layer(UserLayers::at(layers, StackPosition::BeforeRateLimit))
+            .layer(endpoint_rates.layer())
+            .layer(scheduler.layer())
             .buffer(buffer_size)
-            .concurrency_limit(concurrency_limit)
-            .rate_limit(rate_limit_u64, Duration::from_secs(1))
+            .option_layer(admission_metrics)
+            .layer(UserLayers::at(layers, StackPosition::BeforeRetry))
             .retry(retry_policy)
+            .layer(UserLayers::at(layers, StackPosition::AfterRetry))
+            .option_layer(circuit_breaker.as_ref().map(CircuitBreaker::layer))
+            // Below the retries, so every attempt waits for a slot and for a pause after a 429.
+            .layer(rate.limit_layer())
+            .layer(UserLayers::at(layers, StackPosition::AfterRateLimit))
+            .option_layer(dispatch_metrics)
+            .layer(rate.feedback_layer())
             .service(service); // Apply the retry policy and box the service
 
         Ok(RateLimiter {
             service: rate_limited_service,
+            rate,
+            circuit_breaker,
+            coalescer,
+            endpoint_rates,
+            scheduler,
         })
     }
 
@@ -58,6 +156,6 @@ where
         self
         .service
         .call(request)
-        .await.map_err(|e| APIError::from(e))
+        .await.map_err(APIError::from)
     }
 }
\ No newline at end of file
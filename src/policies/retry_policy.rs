@@ -1,45 +1,422 @@
-use futures_util::future::{Ready, Either};
-use tokio::time::{sleep, Duration};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+
+use reqwest::{Method, StatusCode};
+use tokio::time::{sleep_until, Duration, Instant, Sleep};
 use tower::retry::Policy;
-use std::error::Error as StdError;
+use tower::BoxError;
 use crate::error::APIError;
+use crate::policies::adaptive_rate::retry_after;
 use crate::utils::clonable_request::ClonableRequest;
 
 
+/// What a failed attempt says about whether OANDA saw the request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// No connection could be made, so the request was never sent.
+    NotSent,
+    /// The request may have reached OANDA: the connection was reset or timed out, or the
+    /// attempt failed in some other way after it started.
+    Failed,
+    /// OANDA answered with a status that is not a success.
+    Status(StatusCode),
+    /// The circuit breaker refused to send the request. Never retried.
+    CircuitOpen,
+}
+
+impl Outcome {
+    /// Classify the result of one attempt. `None` means it succeeded.
+    pub fn of(result: &Result<reqwest::Response, BoxError>) -> Option<Outcome> {
+        match result {
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) => Some(Outcome::Status(response.status())),
+            Err(error) if matches!(error.downcast_ref::<APIError>(), Some(APIError::CircuitOpen(_))) => {
+                Some(Outcome::CircuitOpen)
+            }
+            Err(error) => {
+                let http = error
+                    .downcast_ref::<reqwest::Error>()
+                    .or_else(|| match error.downcast_ref::<APIError>() {
+                        Some(APIError::HTTP(error)) => Some(error),
+                        _ => None,
+                    });
+                match http {
+                    Some(error) if error.is_connect() => Some(Outcome::NotSent),
+                    _ => Some(Outcome::Failed),
+                }
+            }
+        }
+    }
+}
+
+
+/// Decides which failed attempts are sent again.
+///
+/// A request that was never sent is always safe to retry. Anything else is retried only for
+/// idempotent methods, so an order `POST` that may have reached OANDA is never placed twice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryClassifier {
+    /// Methods that can be sent twice without side effects.
+    pub idempotent_methods: Vec<Method>,
+    /// Statuses retried for idempotent methods.
+    pub retry_statuses: Vec<StatusCode>,
+}
+
+impl RetryClassifier {
+    pub fn is_idempotent(&self, method: &Method) -> bool {
+        self.idempotent_methods.contains(method)
+    }
+
+    pub fn should_retry(&self, method: &Method, outcome: Outcome) -> bool {
+        match outcome {
+            Outcome::NotSent => true,
+            Outcome::CircuitOpen => false,
+            Outcome::Failed => self.is_idempotent(method),
+            Outcome::Status(status) => self.is_idempotent(method) && self.retry_statuses.contains(&status),
+        }
+    }
+}
+
+impl Default for RetryClassifier {
+    /// `GET`, `HEAD` and `OPTIONS` are retried on connection failures, 429 and 5xx gateway errors.
+    fn default() -> Self {
+        RetryClassifier {
+            idempotent_methods: vec![Method::GET, Method::HEAD, Method::OPTIONS],
+            retry_statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+        }
+    }
+}
+
+
+/// How the delay before a retry is randomised, so that callers failing together do not all
+/// retry at the same moment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// Wait exactly the computed delay.
+    None,
+    /// Wait anywhere between zero and the computed delay.
+    #[default]
+    Full,
+    /// Wait at least half the computed delay, and up to all of it.
+    Equal,
+}
+
+
+/// When failed requests are retried and how long to wait before each retry.
+///
+/// The `n`th retry waits `base_delay * multiplier^(n - 1)`, capped at `max_delay` and then
+/// jittered. A 429 with `Retry-After` waits as long as OANDA asks instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryConfig {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: Jitter,
+    /// No retry is scheduled once this much time has passed since the call started.
+    pub max_elapsed: Option<Duration>,
+    pub classifier: RetryClassifier,
+}
+
+impl RetryConfig {
+    /// The delay before retry number `retry` (1 for the first retry), before jitter.
+    pub fn delay(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let delay = self.base_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        if delay.is_finite() && delay < self.max_delay.as_secs_f64() {
+            Duration::from_secs_f64(delay.max(0.0))
+        } else {
+            self.max_delay
+        }
+    }
+
+    /// The delay before retry number `retry`, jittered.
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let delay = self.delay(retry);
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(random_fraction()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(random_fraction()),
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    /// 0.5s, 1s, 2s, ... up to 8s, with full jitter.
+    fn default() -> Self {
+        RetryConfig {
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+            multiplier: 2.0,
+            jitter: Jitter::Full,
+            max_elapsed: None,
+            classifier: RetryClassifier::default(),
+        }
+    }
+}
+
+/// A number in `[0, 1)`. Jitter only has to differ between callers, so the standard library's
+/// randomly keyed hasher is enough.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+
+/// Retries a request up to `attempts` times. The policy holds no per-request state: the attempt
+/// count and start time travel with the request in its `RequestContext`.
+#[derive(Clone, Debug)]
 pub struct RetryPolicy {
     pub attempts: usize,
+    pub config: Arc<RetryConfig>,
 }
 
-impl Clone for RetryPolicy {
-    fn clone(&self) -> Self {
+impl RetryPolicy {
+    pub fn new(attempts: usize) -> RetryPolicy {
+        RetryPolicy::with_config(attempts, RetryConfig::default())
+    }
+
+    pub fn with_config(attempts: usize, config: RetryConfig) -> RetryPolicy {
         RetryPolicy {
-            attempts: self.attempts,
+            attempts,
+            config: Arc::new(config),
         }
     }
 }
 
-impl<Res, E> Policy<ClonableRequest, Res, E> for RetryPolicy
-where
-    E: Into<APIError> + Into<Box<dyn StdError + Send + Sync + 'static>> + Send + Sync + 'static,
-{
-    type Future = Either<Ready<()>, tokio::time::Sleep>;
+impl Policy<ClonableRequest, reqwest::Response, BoxError> for RetryPolicy {
+    type Future = Sleep;
 
-    fn retry(&mut self, _req: &mut ClonableRequest, result: &mut Result<Res, E>) -> Option<Self::Future> {
-        match result {
-            Ok(_) => None, // Don't retry on success
-            Err(_) => {
-                if self.attempts > 0 {
-                    self.attempts -= 1;
-                    let backoff = Duration::from_secs(2u64.pow((self.attempts.min(3)) as u32));
-                    Some(Either::Right(sleep(backoff)))
-                } else {
-                    None // No attempts left, don't retry
-                }
-            }
+    fn retry(&mut self, req: &mut ClonableRequest, result: &mut Result<reqwest::Response, BoxError>) -> Option<Self::Future> {
+        let outcome = Outcome::of(result)?;
+        if !self.config.classifier.should_retry(req.method(), outcome) {
+            return None;
+        }
+        // `attempt` counts the tries made so far, so it is also the number of the next retry.
+        let retry = req.context.attempt;
+        if retry > self.attempts {
+            return None; // No attempts left, don't retry
+        }
+
+        // OANDA says how long to wait on a 429.
+        let backoff = match result {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => retry_after(response),
+            _ => None,
         }
+        .unwrap_or_else(|| self.config.backoff(retry));
+
+        let now = Instant::now();
+        let resume_at = now + backoff;
+        if self
+            .config
+            .max_elapsed
+            .is_some_and(|max_elapsed| resume_at.saturating_duration_since(req.context.started_at) > max_elapsed)
+        {
+            return None;
+        }
+        // The caller would give up before the retry is even sent.
+        if req.context.deadline.is_some_and(|deadline| resume_at >= deadline) {
+            return None;
+        }
+
+        req.context.attempt += 1;
+        req.context.enqueued_at = resume_at;
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            attempt = req.context.attempt,
+            backoff_ms = backoff.as_millis() as u64,
+            ?outcome,
+            "retrying request"
+        );
+        Some(sleep_until(resume_at))
     }
 
     fn clone_request(&mut self, req: &ClonableRequest) -> Option<ClonableRequest> {
         Some(req.clone()) // Use clone for retries
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use tower::layer::layer_fn;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::config::OandaClientBuilder;
+    use crate::environment::Environment;
+    use crate::mock::{MockResponse, MockServer, MOCK_ACCOUNT_ID};
+    use crate::policies::layers::{BoxRequestService, StackPosition};
+
+    #[tokio::test]
+    async fn test_get_retried_on_5xx_and_429() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/summary")
+            .fail_times(1, MockResponse::error(503, None, "Service unavailable"))
+            .mount();
+        server
+            .mock(Method::GET, "/v3/accounts")
+            .fail_times(1, MockResponse::rate_limited(0))
+            .mount();
+        let mut client = server.client_builder().retry_attempts(1).build().unwrap();
+
+        client.get_account_summary().await.unwrap();
+        client.get_accounts().await.unwrap();
+        assert_eq!(server.hits(Method::GET, "/v3/accounts/*/summary"), 2);
+        assert_eq!(server.hits(Method::GET, "/v3/accounts"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_and_posts_not_retried() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::GET, "/v3/accounts/*/instruments")
+            .respond_with(MockResponse::error(404, None, "Not found"));
+        server
+            .mock(Method::POST, "/v3/accounts/*/orders")
+            .respond_with(MockResponse::error(503, None, "Service unavailable"));
+        let mut client = server.client_builder().retry_attempts(1).build().unwrap();
+
+        assert!(matches!(client.get_account_instruments().await, Err(APIError::NotFound(_))));
+        let url = format!("/v3/accounts/{}/orders", MOCK_ACCOUNT_ID);
+        assert!(matches!(client.post(&url, &json!({})).await, Err(APIError::Server(_))));
+        assert_eq!(server.hits(Method::GET, "/v3/accounts/*/instruments"), 1);
+        assert_eq!(server.hits(Method::POST, "/v3/accounts/*/orders"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_custom_classifier() {
+        let server = MockServer::start().await.with_fixtures();
+        server
+            .mock(Method::POST, "/v3/accounts/*/orders")
+            .fail_times(1, MockResponse::error(503, None, "Service unavailable"))
+            .then(MockResponse::ok(json!({})))
+            .mount();
+        let classifier = RetryClassifier {
+            idempotent_methods: vec![Method::GET, Method::POST],
+            ..RetryClassifier::default()
+        };
+        let mut client = server
+            .client_builder()
+            .retry_attempts(1)
+            .retry_classifier(classifier)
+            .build()
+            .unwrap();
+
+        client.post(&format!("/v3/accounts/{}/orders", MOCK_ACCOUNT_ID), &json!({})).await.unwrap();
+        assert_eq!(server.hits(Method::POST, "/v3/accounts/*/orders"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_post_retried_when_never_sent() {
+        // Nothing listens on this port once the listener is dropped.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let mut client = OandaClientBuilder::new()
+            .api_key("token")
+            .account_id(MOCK_ACCOUNT_ID)
+            .environment(Environment::Custom { rest_url: url.clone(), stream_url: url })
+            .retry_attempts(1)
+            .layer(
+                StackPosition::AfterRetry,
+                layer_fn(move |inner: BoxRequestService| {
+                    let counter = counter.clone();
+                    inner.map_request(move |req: ClonableRequest| {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        req
+                    })
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let error = client.post("/v3/accounts/1/orders", &json!({})).await.unwrap_err();
+        assert!(matches!(error, APIError::HTTP(ref e) if e.is_connect()));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_default_classification() {
+        let classifier = RetryClassifier::default();
+        assert!(classifier.should_retry(&Method::POST, Outcome::NotSent));
+        assert!(!classifier.should_retry(&Method::POST, Outcome::Failed));
+        assert!(!classifier.should_retry(&Method::PUT, Outcome::Status(StatusCode::BAD_GATEWAY)));
+        assert!(classifier.should_retry(&Method::GET, Outcome::Failed));
+        assert!(classifier.should_retry(&Method::GET, Outcome::Status(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!classifier.should_retry(&Method::GET, Outcome::Status(StatusCode::BAD_REQUEST)));
+    }
+
+    fn fixed_schedule() -> RetryConfig {
+        RetryConfig {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(400),
+            jitter: Jitter::None,
+            ..RetryConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_schedule() {
+        let config = fixed_schedule();
+        let delays: Vec<u64> = (1..=5).map(|retry| config.backoff(retry).as_millis() as u64).collect();
+        assert_eq!(delays, [100, 200, 400, 400, 400]);
+        assert_eq!(config.delay(usize::MAX), Duration::from_millis(400));
+
+        for _ in 0..100 {
+            let full = RetryConfig { jitter: Jitter::Full, ..fixed_schedule() }.backoff(2);
+            assert!(full <= Duration::from_millis(200));
+            let equal = RetryConfig { jitter: Jitter::Equal, ..fixed_schedule() }.backoff(2);
+            assert!(equal >= Duration::from_millis(100) && equal <= Duration::from_millis(200));
+        }
+    }
+
+    /// Sends a GET through a retry layer whose service always fails, and returns when each try started.
+    async fn attempt_times(policy: RetryPolicy) -> Vec<Duration> {
+        let started = Instant::now();
+        let times = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = times.clone();
+        let service = tower::ServiceBuilder::new().retry(policy).service_fn(move |_req: ClonableRequest| {
+            recorded.lock().unwrap().push(started.elapsed());
+            async { Err::<reqwest::Response, BoxError>(APIError::Other("unavailable".to_string()).into()) }
+        });
+
+        let request = reqwest::Request::new(Method::GET, "http://localhost/v3/accounts".parse().unwrap());
+        service.oneshot(ClonableRequest::new(request)).await.unwrap_err();
+        let times = times.lock().unwrap().clone();
+        times
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_attempts_counted_per_request() {
+        let policy = RetryPolicy::with_config(3, fixed_schedule());
+        let expected: Vec<Duration> = [0, 100, 300, 700].into_iter().map(Duration::from_millis).collect();
+
+        // Both requests share the policy and still get every retry, on the same schedule.
+        let (first, second) = tokio::join!(attempt_times(policy.clone()), attempt_times(policy.clone()));
+        assert_eq!(first, expected);
+        assert_eq!(second, expected);
+        assert_eq!(attempt_times(policy).await, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_elapsed() {
+        let config = RetryConfig { max_elapsed: Some(Duration::from_millis(250)), ..fixed_schedule() };
+        let times = attempt_times(RetryPolicy::with_config(3, config)).await;
+        assert_eq!(times, [Duration::ZERO, Duration::from_millis(100)]);
+    }
+}
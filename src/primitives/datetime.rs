@@ -0,0 +1,152 @@
+//! DateTime fields of the v20 API.
+//!
+//! With the `chrono` feature `DateTime` is a `chrono::DateTime<Utc>`, otherwise it is the
+//! raw string OANDA sent. Model fields use `#[serde(with = "crate::primitives::datetime")]`,
+//! which accepts both the RFC3339 and the UNIX format regardless of the
+//! `Accept-Datetime-Format` the client asked for.
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::error::APIError;
+
+
+#[cfg(feature = "chrono")]
+pub type DateTime = chrono::DateTime<chrono::Utc>;
+
+#[cfg(not(feature = "chrono"))]
+pub type DateTime = String;
+
+
+/// Value of the `Accept-Datetime-Format` header, which selects how OANDA formats
+/// DateTime fields in requests and responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatetimeFormat {
+    /// `2024-08-31T17:58:17.000000000Z`
+    #[default]
+    RFC3339,
+    /// `1725127097.000000000`
+    UNIX,
+}
+
+impl DatetimeFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DatetimeFormat::RFC3339 => "RFC3339",
+            DatetimeFormat::UNIX => "UNIX",
+        }
+    }
+
+    /// Format a DateTime for a request parameter in this format.
+    #[cfg(feature = "chrono")]
+    pub fn format(&self, value: &DateTime) -> String {
+        match self {
+            DatetimeFormat::RFC3339 => value.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+            DatetimeFormat::UNIX => format!("{}.{:09}", value.timestamp(), value.timestamp_subsec_nanos()),
+        }
+    }
+
+    /// Format a DateTime for a request parameter in this format.
+    /// Without the `chrono` feature the string is sent as given.
+    #[cfg(not(feature = "chrono"))]
+    pub fn format(&self, value: &DateTime) -> String {
+        value.clone()
+    }
+}
+
+
+/// Parse a DateTime in either the RFC3339 or the UNIX format.
+#[cfg(feature = "chrono")]
+pub fn parse(s: &str) -> Result<DateTime, APIError> {
+    if let Ok(value) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(value.with_timezone(&chrono::Utc));
+    }
+
+    let invalid = || APIError::Other(format!("Invalid DateTime: {}", s));
+    let (seconds, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let seconds: i64 = seconds.parse().map_err(|_| invalid())?;
+    let nanos: u32 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<9}", fraction).parse().map_err(|_| invalid())?
+    };
+    chrono::DateTime::from_timestamp(seconds, nanos).ok_or_else(invalid)
+}
+
+/// Parse a DateTime in either the RFC3339 or the UNIX format.
+/// Without the `chrono` feature the string is kept as given.
+#[cfg(not(feature = "chrono"))]
+pub fn parse(s: &str) -> Result<DateTime, APIError> {
+    Ok(s.to_string())
+}
+
+
+pub fn serialize<S: Serializer>(value: &DateTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&DatetimeFormat::RFC3339.format(value))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse(&s).map_err(serde::de::Error::custom)
+}
+
+/// Same as the parent module, for optional fields.
+pub mod option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::DateTime;
+
+    pub fn serialize<S: Serializer>(value: &Option<DateTime>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| super::parse(&s).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Event {
+        #[serde(with = "crate::primitives::datetime")]
+        time: DateTime,
+        #[serde(default, with = "crate::primitives::datetime::option")]
+        filled: Option<DateTime>,
+    }
+
+    #[test]
+    fn test_rfc3339_and_unix_decode_to_the_same_value() {
+        let rfc: Event = serde_json::from_str(r#"{"time":"2024-08-31T17:58:17.500000000Z"}"#).unwrap();
+        let unix: Event = serde_json::from_str(r#"{"time":"1725127097.500000000","filled":null}"#).unwrap();
+        assert!(rfc.filled.is_none());
+
+        #[cfg(feature = "chrono")]
+        {
+            assert_eq!(rfc.time, unix.time);
+            assert_eq!(DatetimeFormat::UNIX.format(&rfc.time), "1725127097.500000000");
+            assert_eq!(
+                serde_json::to_string(&unix).unwrap(),
+                r#"{"time":"2024-08-31T17:58:17.500000000Z","filled":null}"#
+            );
+            assert_eq!(parse("0").unwrap().timestamp(), 0);
+            assert!(parse("yesterday").is_err());
+        }
+
+        #[cfg(not(feature = "chrono"))]
+        {
+            assert_eq!(rfc.time, "2024-08-31T17:58:17.500000000Z");
+            assert_eq!(unix.time, "1725127097.500000000");
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Decimal fields of the v20 API.
+//!
+//! OANDA sends prices, units and money amounts as decimal strings. With the `decimal`
+//! feature the newtypes below hold a `rust_decimal::Decimal`, otherwise the string itself.
+//! Either way they serialize back to the exact string OANDA sent, so `"1.10250"` keeps
+//! its trailing zero.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::APIError;
+
+
+#[cfg(feature = "decimal")]
+pub type Decimal = rust_decimal::Decimal;
+
+#[cfg(not(feature = "decimal"))]
+pub type Decimal = String;
+
+
+#[cfg(feature = "decimal")]
+fn parse_decimal(s: &str) -> Result<Decimal, APIError> {
+    Decimal::from_str_exact(s)
+        .or_else(|_| Decimal::from_scientific(s))
+        .map_err(|e| APIError::Other(format!("Invalid decimal {}: {}", s, e)))
+}
+
+#[cfg(not(feature = "decimal"))]
+fn parse_decimal(s: &str) -> Result<Decimal, APIError> {
+    Ok(s.to_string())
+}
+
+
+/// OANDA sends decimals as strings, but a plain JSON number is accepted too.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Raw {
+    String(String),
+    Number(serde_json::Number),
+}
+
+
+macro_rules! decimal_type {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+        pub struct $name(Decimal);
+
+        impl $name {
+            pub fn new(value: Decimal) -> Self {
+                $name(value)
+            }
+
+            pub fn value(&self) -> &Decimal {
+                &self.0
+            }
+
+            pub fn into_inner(self) -> Decimal {
+                self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = APIError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                parse_decimal(s.trim()).map($name)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+
+        impl From<Decimal> for $name {
+            fn from(value: Decimal) -> Self {
+                $name(value)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = match Raw::deserialize(deserializer)? {
+                    Raw::String(s) => s,
+                    Raw::Number(n) => n.to_string(),
+                };
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+decimal_type!(
+    /// A price, such as a candle's close or a trade's fill price.
+    PriceValue
+);
+
+decimal_type!(
+    /// A decimal number without a unit, such as a number of units or a margin rate.
+    DecimalNumber
+);
+
+decimal_type!(
+    /// An amount in the Account's home currency, such as a balance or a P&L.
+    AccountUnits
+);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_keeps_exact_string() {
+        let price: PriceValue = serde_json::from_str(r#""1.10250""#).unwrap();
+        assert_eq!(serde_json::to_string(&price).unwrap(), r#""1.10250""#);
+
+        let units: AccountUnits = serde_json::from_str(r#""-12.3456""#).unwrap();
+        assert_eq!(units.to_string(), "-12.3456");
+
+        let number: DecimalNumber = serde_json::from_str("0.02").unwrap();
+        assert_eq!(number.to_string(), "0.02");
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_decimal_math_is_exact() {
+        let a: AccountUnits = "0.1".parse().unwrap();
+        let b: AccountUnits = "0.2".parse().unwrap();
+        assert_eq!(a.into_inner() + b.into_inner(), "0.3".parse::<Decimal>().unwrap());
+        assert!("1.2.3".parse::<PriceValue>().is_err());
+    }
+}
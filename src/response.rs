@@ -0,0 +1,134 @@
+use std::ops::{Deref, DerefMut};
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+
+pub const REQUEST_ID_HEADER: &str = "RequestID";
+pub const LAST_TRANSACTION_ID_HEADER: &str = "Last-Transaction-ID";
+pub const RATE_LIMIT_LIMIT_HEADER: &str = "X-RateLimit-Limit";
+pub const RATE_LIMIT_REMAINING_HEADER: &str = "X-RateLimit-Remaining";
+pub const RATE_LIMIT_RESET_HEADER: &str = "X-RateLimit-Reset";
+
+
+/// A decoded response together with the status and headers OANDA sent with it.
+///
+/// Returned by `OandaClient::execute_with_response` and the `*_with_response` endpoint methods.
+/// It dereferences to the body, so fields can be read directly:
+///
+/// ```no_run
+/// # async fn run(client: &mut oanda_rs::client::OandaClient) -> Result<(), oanda_rs::error::APIError> {
+/// let response = client.get_account_summary_with_response().await?;
+/// println!("{} (RequestID {:?})", response.account.balance, response.request_id());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Response<T> {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: T,
+}
+
+impl<T> Response<T> {
+    pub fn new(status: StatusCode, headers: HeaderMap, body: T) -> Self {
+        Response { status, headers, body }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// The ID OANDA support asks for when reporting a problem with a request.
+    pub fn request_id(&self) -> Option<&str> {
+        self.header(REQUEST_ID_HEADER)
+    }
+
+    /// The ID of the most recent Transaction on the Account. When it has not moved since the
+    /// last poll, there is nothing to fetch with `get_changes`.
+    pub fn last_transaction_id(&self) -> Option<&str> {
+        self.header(LAST_TRANSACTION_ID_HEADER)
+    }
+
+    /// Requests allowed in the current window, when the server reports it.
+    pub fn rate_limit_limit(&self) -> Option<u64> {
+        self.header(RATE_LIMIT_LIMIT_HEADER).and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Requests left in the current window, when the server reports it.
+    pub fn rate_limit_remaining(&self) -> Option<u64> {
+        self.header(RATE_LIMIT_REMAINING_HEADER).and_then(|v| v.trim().parse().ok())
+    }
+
+    /// When the current window resets, as the server sent it.
+    pub fn rate_limit_reset(&self) -> Option<&str> {
+        self.header(RATE_LIMIT_RESET_HEADER)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.body
+    }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Response<U> {
+        Response {
+            status: self.status,
+            headers: self.headers,
+            body: f(self.body),
+        }
+    }
+}
+
+impl<T> Deref for Response<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.body
+    }
+}
+
+impl<T> DerefMut for Response<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.body
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::fixtures::LAST_TRANSACTION_ID;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn test_response_metadata() {
+        let server = MockServer::start().await.with_fixtures();
+        server.mock(reqwest::Method::GET, "/v3/accounts").respond_with(
+            MockResponse::ok(crate::mock::fixtures::accounts())
+                .header(REQUEST_ID_HEADER, "24829548730538456")
+                .header(LAST_TRANSACTION_ID_HEADER, LAST_TRANSACTION_ID)
+                .header(RATE_LIMIT_REMAINING_HEADER, "99"),
+        );
+        let mut client = server.client();
+
+        let response = client.get_accounts_with_response().await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.request_id(), Some("24829548730538456"));
+        assert_eq!(response.last_transaction_id(), Some(LAST_TRANSACTION_ID));
+        assert_eq!(response.rate_limit_remaining(), Some(99));
+        assert_eq!(response.rate_limit_limit(), None);
+        assert_eq!(response.accounts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_error_carries_request_id() {
+        let server = MockServer::start().await;
+        server.mock(reqwest::Method::GET, "/v3/accounts/*/summary").respond_with(
+            MockResponse::error(404, None, "The Account specified does not exist")
+                .header(REQUEST_ID_HEADER, "42"),
+        );
+        let mut client = server.client();
+
+        let error = client.get_account_summary().await.unwrap_err();
+        assert_eq!(error.request_id(), Some("42"));
+    }
+}
@@ -1,15 +1,66 @@
 use reqwest::Request;
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::Instant;
 
-pub struct ClonableRequest(Request);
+use crate::policies::priority::Priority;
+
+/// What the service stack knows about a request besides the HTTP request itself.
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    /// The path with the account ID put back as `{accountID}`, used to label the request.
+    pub endpoint: String,
+    /// When the call was made; retries keep it.
+    pub started_at: Instant,
+    /// When the request entered the stack, or when its latest retry was scheduled to start.
+    pub enqueued_at: Instant,
+    /// When the request got past the concurrency limit; only tracked with the `metrics` feature.
+    pub admitted_at: Option<Instant>,
+    /// True while the request is counted as queued in front of the concurrency limit; only
+    /// tracked with the `metrics` feature.
+    pub queued: Option<Arc<AtomicBool>>,
+    /// When the caller stops waiting; no retry is scheduled past it.
+    pub deadline: Option<Instant>,
+    /// 1 for the first try, 2 for the first retry, and so on.
+    pub attempt: usize,
+    /// The order in which waiting requests get a concurrency slot.
+    pub priority: Priority,
+}
+
+impl RequestContext {
+    pub fn new(endpoint: &str) -> Self {
+        let now = Instant::now();
+        RequestContext {
+            endpoint: endpoint.to_string(),
+            started_at: now,
+            enqueued_at: now,
+            admitted_at: None,
+            queued: None,
+            deadline: None,
+            attempt: 1,
+            priority: Priority::for_endpoint(endpoint),
+        }
+    }
+}
+
+pub struct ClonableRequest {
+    request: Request,
+    pub context: RequestContext,
+}
 
 impl ClonableRequest {
     pub fn new(request: Request) -> Self {
-        ClonableRequest(request)
+        let context = RequestContext::new(request.url().path());
+        ClonableRequest { request, context }
+    }
+
+    pub fn with_context(request: Request, context: RequestContext) -> Self {
+        ClonableRequest { request, context }
     }
 
     pub fn into_inner(self) -> Request {
-        self.0
+        self.request
     }
 }
 
@@ -17,18 +68,21 @@ impl Deref for ClonableRequest {
     type Target = Request;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.request
     }
 }
 
 impl DerefMut for ClonableRequest {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.request
     }
 }
 
 impl Clone for ClonableRequest {
     fn clone(&self) -> Self {
-        ClonableRequest(self.0.try_clone().unwrap())
+        ClonableRequest {
+            request: self.request.try_clone().unwrap(),
+            context: self.context.clone(),
+        }
     }
-}
\ No newline at end of file
+}